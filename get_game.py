@@ -7,8 +7,8 @@ import os
 import json
 import re
 
-def get_game_with_id(game_id):
-    url = f"https://www.j-archive.com/showgame.php?game_id={game_id}"
+def get_game_with_id(game_id, base_url):
+    url = f"{base_url.rstrip('/')}/showgame.php?game_id={game_id}"
     html = urlopen(url).read()
     html = html.replace(b"&lt;", b"<")
     html = html.replace(b"&gt;", b">")
@@ -69,8 +69,12 @@ def pull_final_from_table(table, name):
 
 if __name__ == '__main__':
     games_root = os.environ.get('J_GAME_ROOT') or 'games'
+    # Base URL of the j-archive site to scrape, e.g. https://www.j-archive.com
+    base_url = os.environ.get('J_ARCHIVE_URL')
+    if not base_url:
+        sys.exit('J_ARCHIVE_URL must be set to the j-archive base URL')
     game_id = sys.argv[1]
-    payload = get_game_with_id(game_id)
+    payload = get_game_with_id(game_id, base_url)
     out_filename = f'{games_root}/{game_id}.json'
     with open(out_filename, 'w') as out_file:
         json.dump(payload, out_file)
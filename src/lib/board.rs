@@ -1,119 +1,467 @@
-use std::sync::Arc;
-
 use futures_util::{SinkExt, StreamExt, TryFutureExt};
-use serde::Deserialize;
-use tokio::sync::{
-    mpsc::{self, UnboundedSender},
-    RwLock,
-};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, UnboundedSender};
 use warp::ws::{Message, WebSocket};
 
-use crate::lib::game::BareRoundType;
-
 use super::{
-    game::{BaseMessage, PlayerMessage, Round, RoundType, StateType},
-    AsyncGameList, Game,
+    format::{Format, Frame},
+    game::{
+        close_for_ws_error, parse_base_message, parse_request, reject_request, PlayerMessage,
+        RoundType, StateType,
+    },
+    sanitize::for_log,
+    AsyncGameList, Game, Round, RoundComplete,
 };
 use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::Instrument;
 
 #[derive(Deserialize)]
 struct PlayerBalanceMessage {
-    request: String,
     player: String,
     amount: i32,
 }
 
 #[derive(Deserialize)]
 struct RevealMessage {
-    request: String,
     row: usize,
     col: usize,
 }
 
+#[derive(Serialize)]
+struct BalanceSetMessage<'a> {
+    message: &'a str,
+    player: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct WaitingForPlayersMessage<'a> {
+    message: &'a str,
+    have: usize,
+    need: usize,
+}
+
+#[derive(Serialize)]
+struct RoundMetaMessage<'a> {
+    message: &'a str,
+    name: &'a str,
+    daily_doubles: usize,
+}
+
+#[derive(Serialize)]
+struct MediaManifestMessage<'a> {
+    message: &'a str,
+    urls: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct ReadyCountMessage<'a> {
+    message: &'a str,
+    ready: usize,
+    need: usize,
+}
+
+#[derive(Serialize)]
+struct DailyDoubleMessage<'a> {
+    message: &'a str,
+    player: Option<&'a str>,
+}
+
 impl Game {
-    fn board_connected(&mut self, tx: UnboundedSender<Message>) -> Result<(), ()> {
+    fn board_connected(&mut self, tx: UnboundedSender<Frame>) -> Result<(), ()> {
         if self.board_tx.is_some() {
-            println!("attempted to connect board, but there's already ony connected");
+            tracing::warn!("attempted to connect board, but there's already ony connected");
             Err(())
         } else {
-            println!("connecting board");
+            tracing::info!("connecting board");
             self.board_tx = Some(tx);
+            self.joined = true;
             Ok(())
         }
     }
     fn board_disconnected(&mut self) {
-        println!("removing board socket");
+        tracing::info!("removing board socket");
         if let Some(tx) = &self.board_tx {
-            tx.send(Message::close());
+            let _ = tx.send(Frame::close());
         }
         self.board_tx = None;
         self.send_state();
     }
 
-    fn next_round(&mut self) {
+    pub fn next_round(&mut self) {
+        if self.state.round_idx + 1 >= self.rounds.len() {
+            return;
+        }
+        self.archive_round_summary();
         self.state.round_idx += 1;
         self.state.clues_shown = 0;
+        self.state.final_clue_idx = 0;
+        self.state.choices = None;
         let new_round = &self.rounds[self.state.round_idx];
         self.state.bare_round = new_round.clone().to_bare_round();
         if let RoundType::FinalRound { category, .. } = new_round {
             self.state.category = category.to_string();
-            self.state.state_type = StateType::FinalWager;
+            if self.state.players.values().any(|p| p.tx.is_some()) {
+                self.state.state_type = StateType::FinalWager;
+            } else {
+                // Nobody is around to wager, so skip straight to the results
+                // rather than waiting forever.
+                tracing::info!("no players connected for the final round");
+                self.state.state_type = StateType::Response;
+            }
         } else {
             self.state.state_type = StateType::Board;
         }
         self.send_state();
+        self.send_round_meta();
+        self.send_media_manifest();
+    }
+
+    /// Tells the host and board about the current round without giving away
+    /// where anything is.
+    pub fn send_round_meta(&self) {
+        let round = &self.rounds[self.state.round_idx];
+        let name = round.get_name();
+        let msg = RoundMetaMessage {
+            message: "round_meta",
+            name: &name,
+            daily_doubles: round.daily_double_count(),
+        };
+        match serde_json::to_string(&msg) {
+            Ok(s) => {
+                let msg = Frame::text(s);
+                self.send_to_board(msg.clone());
+                self.send_to_host(msg);
+            }
+            Err(e) => tracing::error!("Error serializing round meta: {}", e),
+        }
+    }
+
+    /// Lists the current round's media URLs so the board can preload them
+    /// before any clue is revealed. Nothing is sent for rounds without media.
+    fn send_media_manifest(&self) {
+        let urls = self.rounds[self.state.round_idx].media_urls();
+        if urls.is_empty() {
+            return;
+        }
+
+        let msg = MediaManifestMessage {
+            message: "media_manifest",
+            urls,
+        };
+        match serde_json::to_string(&msg) {
+            Ok(s) => self.send_to_board(Frame::text(s)),
+            Err(e) => tracing::error!("Error serializing media manifest: {}", e),
+        }
+    }
+
+    /// Puts the current round's categories in a random order. Only allowed
+    /// before any of its clues have been revealed.
+    fn shuffle_categories(&mut self) {
+        if self.state.clues_shown != 0 {
+            tracing::warn!("not shuffling categories: clues have already been revealed");
+            return;
+        }
+        let categories = match &mut self.rounds[self.state.round_idx] {
+            RoundType::DefaultRound { categories, .. } => categories,
+            RoundType::FinalRound { .. } => return,
+        };
+        categories.shuffle(&mut self.rng);
+        self.state.bare_round = self.rounds[self.state.round_idx].clone().to_bare_round();
+        self.send_categories();
+        self.send_state();
+    }
+
+    /// Puts every clue of the current round back on the board for a do-over.
+    /// Balances are left as they are.
+    pub fn reset_round(&mut self) {
+        if let RoundType::FinalRound { .. } = self.rounds[self.state.round_idx] {
+            return;
+        }
+        tracing::info!("resetting round {}", self.state.round_idx);
+        self.drop_clue_in_play();
+        self.state.clues_shown = 0;
+        self.points_won.clear();
+        self.state.cell_outcomes.clear();
+        self.send_state();
+    }
+
+    /// Abandons whatever clue is in play, stopping its timers, and goes back
+    /// to the board. Doesn't send the state.
+    pub fn drop_clue_in_play(&mut self) {
+        self.cancel_timer();
+        self.state.current_cell = None;
+        self.state.wagered_clue = false;
+        self.state.buzzers_open = false;
+        self.state.buzzed_player = None;
+        self.state.buzzed_at = None;
+        self.state.responded_players.clear();
+        self.state.state_type = StateType::Board;
+    }
+
+    fn is_round_complete(&self) -> bool {
+        match &self.rounds[self.state.round_idx] {
+            RoundType::DefaultRound { categories, .. } => {
+                categories.iter().enumerate().all(|(col, category)| {
+                    (0..category.clues.len()).all(|row| self.state.is_clue_shown(row, col))
+                })
+            }
+            RoundType::FinalRound { .. } => false,
+        }
+    }
+
+    /// Called when a clue is over, to act on a round whose clues have all
+    /// been played. Returns true if it moved on to the next round, which
+    /// sends the state itself.
+    pub fn check_round_complete(&mut self) -> bool {
+        if !self.is_round_complete() {
+            return false;
+        }
+        match self.config.on_round_complete {
+            RoundComplete::Manual => false,
+            RoundComplete::Prompt => {
+                self.send_to_all(Frame::text(r#"{"message":"round_complete"}"#));
+                false
+            }
+            RoundComplete::Auto => {
+                self.next_round();
+                true
+            }
+        }
+    }
+
+    /// How many players are ready, and how many need to be before the game
+    /// can start.
+    fn ready_count(&self) -> (usize, usize) {
+        let connected = self.state.players.values().filter(|p| p.tx.is_some());
+        let ready = connected
+            .clone()
+            .filter(|p| self.state.ready_players.contains(&p.name))
+            .count();
+        let need = match self.config.ready_quorum {
+            Some(quorum) => quorum,
+            None => connected.count(),
+        };
+        (ready, need.max(1))
+    }
+
+    pub fn send_ready_count(&self) {
+        if !self.config.require_ready {
+            return;
+        }
+        let (ready, need) = self.ready_count();
+        let msg = ReadyCountMessage {
+            message: "ready_count",
+            ready,
+            need,
+        };
+        if let Ok(s) = serde_json::to_string(&msg) {
+            self.send_to_all(Frame::text(s));
+        }
+    }
+
+    /// Whether the game has to wait for more players to be ready. Only the
+    /// first clue of the game is held back.
+    fn waiting_for_ready(&self) -> bool {
+        if !self.config.require_ready || self.state.round_idx != 0 || self.state.clues_shown != 0 {
+            return false;
+        }
+        let (ready, need) = self.ready_count();
+        ready < need
     }
 
     fn remove_player(&mut self, player: String) {
         if let Some(Some(tx)) = self.state.players.remove(&player).map(|p| p.tx) {
-            tx.send(Message::close());
+            let _ = tx.send(Frame::close());
         }
         self.state.wagers.remove(&player);
         self.state.player_responses.remove(&player);
+        self.state.ready_players.remove(&player);
+        self.state.responded_players.remove(&player);
+        if self.state.active_player.as_ref() == Some(&player) {
+            self.set_active_player(None);
+        }
+        if self.state.buzzed_player.as_ref() == Some(&player) {
+            self.cancel_timer();
+            self.state.buzzed_player = None;
+            self.state.buzzers_open = self.state.state_type == StateType::Clue;
+        }
         self.send_state();
     }
 
-    fn set_player_balance(&mut self, player: String, amount: i32) {
-        self.state
-            .players
-            .entry(player)
-            .and_modify(|p| p.balance = amount);
+    /// Sets a player's balance outright, telling the board whether it took
+    /// effect. Returns false if there is no such player.
+    fn set_player_balance(&mut self, player: String, amount: i32) -> bool {
+        let amount = match self.config.max_balance_magnitude {
+            Some(max) => {
+                let max = i32::try_from(max).unwrap_or(i32::MAX);
+                amount.clamp(-max, max)
+            }
+            None => amount,
+        };
+
+        let now = self.now();
+        let (message, amount) = match self.state.players.get_mut(&player) {
+            Some(p) => {
+                p.set_balance(amount, now);
+                ("balance_set", Some(amount))
+            }
+            None => {
+                tracing::warn!("can't set balance of unknown player {}", for_log(&player));
+                ("unknown_player", None)
+            }
+        };
+
+        let msg = BalanceSetMessage {
+            message,
+            player: &player,
+            amount,
+        };
+        if let Ok(s) = serde_json::to_string(&msg) {
+            self.send_to_board(Frame::text(s));
+        }
+
+        if amount.is_none() {
+            return false;
+        }
         self.send_state();
+        true
+    }
+
+    /// The first clue of the current round not yet revealed, going along
+    /// each row in turn.
+    fn next_unrevealed_cell(&self) -> Option<(usize, usize)> {
+        let categories = match &self.rounds[self.state.round_idx] {
+            RoundType::DefaultRound { categories, .. } => categories,
+            RoundType::FinalRound { .. } => return None,
+        };
+        let (cols, rows) = self.rounds[self.state.round_idx].dimensions()?;
+        (0..rows)
+            .flat_map(|row| (0..cols).map(move |col| (row, col)))
+            .find(|&(row, col)| {
+                row < categories[col].clues.len() && !self.state.is_clue_shown(row, col)
+            })
     }
 
-    fn reveal(&mut self, row: usize, col: usize) {
+    /// Reveals the next clue in order, for playing through a round without
+    /// picking cells. Returns false if nothing was revealed.
+    pub fn reveal_next(&mut self) -> bool {
+        match self.next_unrevealed_cell() {
+            Some((row, col)) => self.reveal(row, col),
+            None => false,
+        }
+    }
+
+    /// Shows the clue at (row, col), returning false if nothing was revealed.
+    pub fn reveal(&mut self, row: usize, col: usize) -> bool {
         let board = &self.rounds[self.state.round_idx];
         let categories = match board {
-            RoundType::FinalRound { .. } => return,
+            RoundType::FinalRound { .. } => return false,
             RoundType::DefaultRound { categories, .. } => categories,
         };
 
-        if row > 5 || col > 6 {
-            return;
+        if col >= categories.len() || row >= categories[col].clues.len() {
+            return false;
+        }
+
+        if self.state.state_type != StateType::Board {
+            tracing::warn!("not revealing: a clue is already in play");
+            return false;
+        }
+
+        let have = self.state.players.len();
+        let need = self.config.min_players_to_start;
+        if have < need {
+            tracing::info!("not revealing: {} of {} players have joined", have, need);
+            let msg = WaitingForPlayersMessage {
+                message: "waiting_for_players",
+                have,
+                need,
+            };
+            if let Ok(s) = serde_json::to_string(&msg) {
+                self.send_to_all(Frame::text(s));
+            }
+            return false;
         }
 
-        let bitset_key = 1 << (row * 6 + col);
+        if self.waiting_for_ready() {
+            tracing::info!("not revealing: waiting for players to be ready");
+            self.send_ready_count();
+            return false;
+        }
+
+        if self.state.is_clue_shown(row, col) {
+            tracing::warn!("clue at row {} col {} was already revealed", row, col);
+            return false;
+        }
 
         let clue_obj = &categories[col].clues[row];
         self.state.clue = clue_obj.clue.clone();
         self.state.response = clue_obj.response.clone();
+        self.state.media_url = clue_obj.media_url.clone();
+        self.state.choices = clue_obj.choices.clone();
+        self.state.chosen = None;
+        self.state.suggested_correct = None;
         self.state.category = categories[col].category.clone();
-        self.state.cost = clue_obj.cost;
+        self.state.cost = clue_obj.cost.saturating_mul(board.value_multiplier());
+        // A new clue starts with a clean slate, whatever happened on the last.
+        self.state.responded_players.clear();
+        self.state.buzzed_player = None;
         self.state.state_type = if clue_obj.is_daily_double {
             StateType::DailyDouble
+        } else if clue_obj.is_wager {
+            StateType::Wager
         } else {
             StateType::Clue
         };
 
-        self.state.clues_shown |= bitset_key;
+        self.state.clues_shown |= self.state.clue_key(row, col);
+        self.state.current_cell = Some((row, col));
+        self.state.wagered_clue = false;
+        self.cancel_timer();
+
+        if self.state.state_type == StateType::DailyDouble {
+            self.send_daily_double();
+        }
+        true
+    }
+
+    /// Announces a Daily Double as a discrete event so the board can play its
+    /// reveal at the right moment rather than inferring it from the state.
+    fn send_daily_double(&self) {
+        let msg = DailyDoubleMessage {
+            message: "daily_double",
+            player: self.state.active_player.as_deref(),
+        };
+        let msg = match serde_json::to_string(&msg) {
+            Ok(s) => Frame::text(s),
+            Err(e) => {
+                tracing::error!("Error serializing daily double: {}", e);
+                return;
+            }
+        };
+
+        if self.config.announce_daily_double_to_players {
+            self.send_to_players(msg.clone());
+        }
+        self.send_to_board(msg);
     }
 }
 
-pub async fn board_connected(games: AsyncGameList, lobby_id: String, ws: WebSocket) {
+#[tracing::instrument(skip(games, ws))]
+pub async fn board_connected(
+    games: AsyncGameList,
+    lobby_id: String,
+    ws: WebSocket,
+    format: Format,
+    catchup: bool,
+) {
     let game = match games.read().await.get(&lobby_id) {
         Some(Some(g)) => g.clone(),
         _ => {
-            ws.close().await;
+            let _ = ws.close().await;
             return;
         }
     };
@@ -121,26 +469,34 @@ pub async fn board_connected(games: AsyncGameList, lobby_id: String, ws: WebSock
     let (tx, rx) = mpsc::unbounded_channel();
     let mut rx = UnboundedReceiverStream::new(rx);
 
-    if game.write().await.board_connected(tx).is_err() {
+    if game.write().await.board_connected(tx.clone()).is_err() {
         // There is already a board connected
-        ws_tx.send(Message::close()).await;
+        let _ = ws_tx.send(Message::close()).await;
         return;
     }
 
-    tokio::task::spawn(async move {
-        while let Some(message) = rx.next().await {
-            ws_tx
-                .send(message)
-                .unwrap_or_else(|e| {
-                    eprintln!("websocket send error: {}", e);
-                })
-                .await;
+    tokio::task::spawn(
+        async move {
+            while let Some(message) = rx.next().await {
+                ws_tx
+                    .send(format.encode(&message))
+                    .unwrap_or_else(|e| {
+                        tracing::error!("websocket send error: {}", e);
+                    })
+                    .await;
+            }
         }
-    });
+        .in_current_span(),
+    );
 
     {
         let game = game.read().await;
         game.send_categories();
+        game.send_round_meta();
+        game.send_media_manifest();
+        if catchup {
+            game.send_recent_states();
+        }
         game.send_state();
     }
 
@@ -148,78 +504,386 @@ pub async fn board_connected(games: AsyncGameList, lobby_id: String, ws: WebSock
         let msg = match message {
             Ok(s) => s,
             Err(e) => {
-                eprintln!("Websocket error: {}", e);
+                tracing::error!("Websocket error: {}", e);
+                if let Some(close) = close_for_ws_error(&e) {
+                    let _ = tx.send(close);
+                }
                 break;
             }
         };
 
-        let txt = match msg.to_str() {
-            Ok(s) => s,
-            Err(_) => {
+        let txt = match format.decode(&msg) {
+            Some(s) => s,
+            None => {
                 if msg.is_close() {
-                    println!("board client disconnected");
+                    tracing::info!("board client disconnected");
                     game.write().await.board_disconnected();
                     break;
                 }
-                eprintln!("Received non-text Websocket message");
+                tracing::error!("Received non-text Websocket message");
                 continue;
             }
         };
 
-        let msg: BaseMessage = match serde_json::from_str(txt) {
-            Ok(m) => m,
-            Err(e) => {
-                eprintln!("Deserialization Error: {}", e);
-                break;
-            }
+        let msg = match parse_base_message(&txt, &tx) {
+            Some(m) => m,
+            None => break,
         };
 
         let mut game = game.write().await;
+        game.touch();
         match msg.request.as_str() {
             "next_round" => game.next_round(),
+            "reset_round" => game.reset_round(),
+            "shuffle_categories" => game.shuffle_categories(),
             "response" => game.show_response(),
             "board" => {
+                // A clue that ended with its response shown was already
+                // checked then.
+                let skipped_response = matches!(
+                    game.state.state_type,
+                    StateType::Clue
+                        | StateType::ClueLocked
+                        | StateType::DailyDouble
+                        | StateType::Wager
+                );
                 game.state.state_type = StateType::Board;
-                game.send_state();
+                if skipped_response && game.check_round_complete() {
+                    continue;
+                }
+            }
+            "sync" => {
+                if let Some(tx) = &game.board_tx {
+                    game.sync(tx, true);
+                }
+                continue;
             }
             "remove" => {
-                let msg: PlayerMessage = match serde_json::from_str(txt) {
-                    Ok(m) => m,
-                    Err(e) => {
-                        eprintln!("Deserialization Error: {}", e);
-                        continue;
-                    }
+                let msg: PlayerMessage = match parse_request(&txt, &tx) {
+                    Some(m) => m,
+                    None => continue,
                 };
 
                 game.remove_player(msg.player);
             }
             "set_player_balance" => {
-                let msg: PlayerBalanceMessage = match serde_json::from_str(txt) {
-                    Ok(m) => m,
-                    Err(e) => {
-                        eprintln!("Deserialization Error: {}", e);
-                        continue;
-                    }
+                let msg: PlayerBalanceMessage = match parse_request(&txt, &tx) {
+                    Some(m) => m,
+                    None => continue,
                 };
 
-                game.set_player_balance(msg.player, msg.amount);
+                if !game.set_player_balance(msg.player, msg.amount) {
+                    continue;
+                }
             }
             "reveal" => {
-                let msg: RevealMessage = match serde_json::from_str(txt) {
-                    Ok(m) => m,
-                    Err(e) => {
-                        eprintln!("Deserialization Error: {}", e);
-                        continue;
-                    }
+                let msg: RevealMessage = match parse_request(&txt, &tx) {
+                    Some(m) => m,
+                    None => continue,
                 };
 
-                game.state.state_type = StateType::Clue;
-                game.reveal(msg.row, msg.col);
+                if !game.reveal(msg.row, msg.col) {
+                    continue;
+                }
+            }
+            "reveal_next" => {
+                if !game.reveal_next() {
+                    continue;
+                }
+            }
+            _ => {
+                reject_request(&msg.request, &tx);
+                continue;
             }
-            _ => {}
         };
+        game.enforce_invariants();
         game.send_state();
     }
 
     game.write().await.board_disconnected();
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use serde_json::json;
+    use tokio::{sync::RwLock, time::Instant};
+    use tokio_tungstenite::{connect_async, tungstenite};
+    use warp::Filter;
+
+    use super::*;
+    use crate::lib::{
+        game::MESSAGE_TOO_BIG_CLOSE_CODE,
+        harness::{drain, last, test_definition, test_game, TestClients},
+        MockClock,
+    };
+
+    /// A seed whose shuffle swaps the two categories of the test round.
+    const SHUFFLE_SEED: u64 = 2;
+
+    /// The category order a game seeded with `seed` shuffles to.
+    async fn shuffled_order(seed: u64) -> Vec<String> {
+        let clock = Arc::new(MockClock::new(0));
+        let game = Game::new(test_definition(json!({})), clock, Some(seed));
+        let mut game = game.write().await;
+        game.shuffle_categories();
+        game.rounds[0].get_categories()
+    }
+
+    #[tokio::test]
+    async fn shuffling_with_a_seed_is_deterministic() {
+        let order = shuffled_order(SHUFFLE_SEED).await;
+        assert_eq!(order, ["Peaks", "Rivers"]);
+        assert_eq!(shuffled_order(SHUFFLE_SEED).await, order);
+    }
+
+    #[tokio::test]
+    async fn shuffled_cells_keep_their_clues() {
+        let clock = Arc::new(MockClock::new(0));
+        let game = Game::new(test_definition(json!({})), clock, Some(SHUFFLE_SEED));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice"]);
+        drain(&mut clients.board);
+
+        game.shuffle_categories();
+        let sent = drain(&mut clients.board);
+        let find = |kind: &str| sent.iter().find(|msg| msg["message"] == kind).unwrap();
+        assert_eq!(find("categories")["categories"], json!(["Peaks", "Rivers"]));
+        let shown: Vec<_> = find("state")["bare_round"]["categories"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["category"].clone())
+            .collect();
+        assert_eq!(shown, [json!("Peaks"), json!("Rivers")]);
+
+        assert!(game.reveal(1, 0));
+        assert_eq!(game.state.clue, "Tallest in Africa");
+        assert_eq!(game.state.response, "Kilimanjaro");
+        assert_eq!(game.state.clues_shown, 1 << 2);
+
+        game.drop_clue_in_play();
+        assert!(game.reveal(1, 1));
+        assert_eq!(game.state.state_type, StateType::DailyDouble);
+        assert_eq!(game.state.response, "the Danube");
+    }
+
+    #[tokio::test]
+    async fn revealing_a_shown_clue_is_ignored() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice"]);
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+        game.player_buzz("alice", Instant::now());
+        game.correct(true);
+        game.state.state_type = StateType::Board;
+        drain(&mut clients.board);
+
+        assert!(!game.reveal(0, 0));
+        assert_eq!(game.state.state_type, StateType::Board);
+        assert_eq!(game.state.players["alice"].balance, 200);
+        assert!(drain(&mut clients.board).is_empty());
+        assert!(game.reveal(0, 1));
+    }
+
+    #[tokio::test]
+    async fn reveals_only_happen_from_the_board() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice"]);
+        assert!(game.reveal(0, 0));
+
+        assert!(!game.reveal(0, 1));
+        assert!(!game.reveal_next());
+        assert_eq!(game.state.clue, "Longest in Africa");
+        assert_eq!(game.state.state_type, StateType::Clue);
+
+        game.show_response();
+        assert!(!game.reveal(0, 1));
+        assert!(!game.reveal_next());
+        assert_eq!(game.state.clue, "Longest in Africa");
+
+        game.state.state_type = StateType::Board;
+        assert!(game.reveal_next());
+        assert_eq!(game.state.clue, "Tallest on Earth");
+    }
+
+    #[tokio::test]
+    async fn everyone_can_buzz_on_the_clue_after_a_daily_double() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice", "bob"]);
+        game.set_active_player(Some("alice".to_string()));
+        assert!(game.reveal(1, 0));
+        game.wager("alice".to_string(), 500);
+        assert_eq!(game.state.responded_players.len(), 2);
+        game.correct(false);
+        game.state.state_type = StateType::Board;
+
+        assert!(game.reveal(0, 1));
+        assert!(game.state.responded_players.is_empty());
+        game.set_buzzers_open(true);
+        game.player_buzz("bob", Instant::now());
+        assert_eq!(game.state.buzzed_player.as_deref(), Some("bob"));
+    }
+
+    #[tokio::test]
+    async fn final_round_with_nobody_connected_goes_to_results() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice"]);
+        game.state.players.get_mut("alice").unwrap().tx = None;
+
+        game.next_round();
+        assert_eq!(game.state.state_type, StateType::Response);
+        assert!(game.is_game_over());
+        let state = last(&mut clients.board, "state").unwrap();
+        assert_eq!(state["state_type"], "Response");
+    }
+
+    #[tokio::test]
+    async fn final_round_with_players_collects_wagers() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice"]);
+
+        game.next_round();
+        assert_eq!(game.state.state_type, StateType::FinalWager);
+    }
+
+    /// A game where only the clue at (0, 0) is left, with alice buzzed in
+    /// on it.
+    async fn last_clue_buzzed(config: serde_json::Value) -> (Arc<RwLock<Game>>, TestClients) {
+        let (game, _clock) = test_game(config);
+        let clients = {
+            let mut game = game.write().await;
+            let mut clients = game.attach_test_clients(&["alice"]);
+            game.state.clues_shown = 0b1110;
+            assert!(game.reveal(0, 0));
+            game.set_buzzers_open(true);
+            game.player_buzz("alice", Instant::now());
+            drain(&mut clients.board);
+            clients
+        };
+        (game, clients)
+    }
+
+    #[tokio::test]
+    async fn scoring_the_last_clue_prompts_once() {
+        let (game, mut clients) = last_clue_buzzed(json!({ "on_round_complete": "prompt" })).await;
+        let mut game = game.write().await;
+        game.correct(true);
+        let sent = drain(&mut clients.board);
+        let prompts = sent.iter().filter(|m| m["message"] == "round_complete");
+        assert_eq!(prompts.count(), 1);
+        assert_eq!(game.state.state_type, StateType::Response);
+    }
+
+    #[tokio::test]
+    async fn scoring_the_last_clue_advances_with_one_state() {
+        let (game, mut clients) = last_clue_buzzed(json!({ "on_round_complete": "auto" })).await;
+        let mut game = game.write().await;
+        game.correct(true);
+        let states: Vec<_> = drain(&mut clients.board)
+            .into_iter()
+            .filter(|m| m["message"] == "state")
+            .collect();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0]["state_type"], "FinalWager");
+        assert_eq!(game.state.players["alice"].balance, 200);
+    }
+
+    #[tokio::test]
+    async fn scoring_a_clue_mid_round_does_nothing_extra() {
+        let (game, _clock) = test_game(json!({ "on_round_complete": "auto" }));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice"]);
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+        game.player_buzz("alice", Instant::now());
+        game.correct(true);
+        assert_eq!(game.state.state_type, StateType::Response);
+        assert_eq!(game.state.round_idx, 0);
+    }
+
+    #[tokio::test]
+    async fn set_balance_is_clamped_to_the_magnitude() {
+        let (game, _clock) = test_game(json!({ "max_balance_magnitude": 500 }));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice"]);
+
+        assert!(game.set_player_balance("alice".to_string(), 1000));
+        assert_eq!(game.state.players["alice"].balance, 500);
+        assert!(game.set_player_balance("alice".to_string(), -1000));
+        assert_eq!(game.state.players["alice"].balance, -500);
+        let set = last(&mut clients.board, "balance_set").unwrap();
+        assert_eq!(set["amount"], -500);
+
+        game.config.max_balance_magnitude = Some(u32::MAX);
+        assert!(game.set_player_balance("alice".to_string(), i32::MIN));
+        assert_eq!(game.state.players["alice"].balance, -i32::MAX);
+    }
+
+    #[test]
+    fn negative_balance_magnitude_is_refused_on_load() {
+        let config = json!({ "max_balance_magnitude": -500 });
+        let def = serde_json::to_string(&json!({ "rounds": [], "config": config })).unwrap();
+        assert!(serde_json::from_str::<crate::GameDefinition>(&def).is_err());
+    }
+
+    #[tokio::test]
+    async fn setting_an_unknown_players_balance_changes_nothing() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice"]);
+        drain(&mut clients.board);
+        drain(&mut clients.host);
+
+        assert!(!game.set_player_balance("bob".to_string(), 100));
+        assert_eq!(
+            drain(&mut clients.board),
+            vec![json!({ "message": "unknown_player", "player": "bob" })]
+        );
+        assert!(drain(&mut clients.host).is_empty());
+        assert!(drain(clients.players.get_mut("alice").unwrap()).is_empty());
+        assert_eq!(game.state.players.len(), 1);
+        assert_eq!(game.state.players["alice"].balance, 0);
+    }
+
+    #[tokio::test]
+    async fn oversized_message_is_closed_with_1009() {
+        let (game, _clock) = test_game(json!({}));
+        let games: AsyncGameList = Arc::new(RwLock::new(HashMap::from([(
+            "lobby".to_string(),
+            Some(game),
+        )])));
+        let route = warp::ws().map(move |ws: warp::ws::Ws| {
+            let games = games.clone();
+            ws.max_message_size(64)
+                .max_frame_size(64)
+                .on_upgrade(move |ws| {
+                    board_connected(games, "lobby".to_string(), ws, Format::Json, false)
+                })
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let (mut client, _) = connect_async(format!("ws://{}/", addr)).await.unwrap();
+        client
+            .send(tungstenite::Message::Text("x".repeat(65)))
+            .await
+            .unwrap();
+        let close = loop {
+            match client.next().await.unwrap().unwrap() {
+                tungstenite::Message::Close(frame) => break frame,
+                _ => continue,
+            }
+        };
+        assert_eq!(
+            close.map(|f| u16::from(f.code)),
+            Some(MESSAGE_TOO_BIG_CLOSE_CODE)
+        );
+    }
+}
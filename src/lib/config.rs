@@ -0,0 +1,154 @@
+use serde::Deserialize;
+
+/// What happens once the last clue of a round has been played.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundComplete {
+    /// Nothing; the board advances with "next_round" as usual.
+    #[default]
+    Manual,
+    /// Send a `round_complete` event so clients can offer to advance.
+    Prompt,
+    /// Advance to the next round straight away, without showing the last
+    /// clue's response.
+    Auto,
+}
+
+/// How much a wrong response costs the player who buzzed in.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WrongAnswerPenalty {
+    /// The full value of the clue.
+    #[default]
+    Full,
+    /// Nothing.
+    None,
+    /// This fraction of the clue's value, rounded toward zero.
+    Fraction(f32),
+}
+
+impl WrongAnswerPenalty {
+    pub fn apply(self, cost: i32) -> i32 {
+        match self {
+            WrongAnswerPenalty::Full => cost,
+            WrongAnswerPenalty::None => 0,
+            WrongAnswerPenalty::Fraction(f) => (cost as f32 * f) as i32,
+        }
+    }
+}
+
+/// Per-game options, read from the optional `config` object of a game
+/// definition. Every field has a default so existing games load unchanged.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct GameConfig {
+    /// Also send the `daily_double` event to players instead of only the board.
+    pub announce_daily_double_to_players: bool,
+    /// How long after buzzing a player may take the buzz back, in
+    /// milliseconds. Zero disables un-buzzing.
+    pub unbuzz_grace_ms: u64,
+    /// Clues can't be revealed until this many players have joined.
+    pub min_players_to_start: usize,
+    /// Emotes players are allowed to send.
+    pub emote_allowlist: Vec<String>,
+    /// Minimum time between two emotes from the same player, in milliseconds.
+    pub emote_cooldown_ms: u64,
+    /// Balances set directly by the board are clamped to this magnitude.
+    pub max_balance_magnitude: Option<u32>,
+    pub on_round_complete: RoundComplete,
+    /// Seconds a buzzed player has to be judged before they're counted
+    /// wrong. Zero disables the timer.
+    pub answer_timeout_secs: u64,
+    /// Whether players with a negative balance may buzz in.
+    pub allow_negative_buzz: bool,
+    /// Hold the first clue back until players have said they're ready.
+    pub require_ready: bool,
+    /// How many players must be ready with `require_ready` on. Every
+    /// connected player must be if unset.
+    pub ready_quorum: Option<usize>,
+    /// Compare final responses against the correct one and suggest a
+    /// verdict to the host, who still makes the call.
+    pub auto_judge: bool,
+    /// Keep each player's balance from the other players until the game is
+    /// over. The host and board always see every balance.
+    pub hide_balances: bool,
+    /// What a wrong response costs on a regular clue: the full value,
+    /// nothing, or a fraction of it between 0 and 1. Final round wagers
+    /// are always lost in full whatever this is.
+    pub wrong_answer_penalty: WrongAnswerPenalty,
+    /// Whether a player the host passes on may buzz in again on the same
+    /// clue.
+    pub pass_allows_rebuzz: bool,
+    /// Let more than one host connect. Only the lead host, the earliest
+    /// still connected, can run the game unless `co_hosts_can_command` is on.
+    pub allow_co_hosts: bool,
+    /// Take commands from every connected host, not just the lead.
+    pub co_hosts_can_command: bool,
+    /// New players are turned away once the game has this many. Players
+    /// who are already in it can always reconnect.
+    pub max_players: Option<usize>,
+    /// How long after the first buzz on a clue to keep collecting buzzes,
+    /// in milliseconds, before awarding it to whichever arrived earliest.
+    /// This evens out who happens to get the game lock first, at the cost of
+    /// delaying every buzz by the window. Zero awards the first buzz
+    /// straight away.
+    pub buzz_window_ms: u64,
+    /// Player names that can't be used, matched case-insensitively. An entry
+    /// wrapped in `*`, like `*host*`, bans any name containing it; any other
+    /// entry only bans that exact name. Added to the server-wide list in
+    /// BANNED_PLAYER_NAMES.
+    pub banned_names: Vec<String>,
+    /// Avatars players may pick from when they join.
+    pub avatar_allowlist: Vec<String>,
+    /// The most anyone can wager on a Daily Double, however large their
+    /// balance.
+    pub daily_double_cap: Option<i32>,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            announce_daily_double_to_players: false,
+            unbuzz_grace_ms: 0,
+            min_players_to_start: 1,
+            emote_allowlist: ["👏", "😂", "😮", "🤔", "🔥", "😬"]
+                .iter()
+                .map(|e| e.to_string())
+                .collect(),
+            emote_cooldown_ms: 2000,
+            max_balance_magnitude: None,
+            on_round_complete: RoundComplete::Manual,
+            answer_timeout_secs: 0,
+            allow_negative_buzz: true,
+            require_ready: false,
+            ready_quorum: None,
+            auto_judge: false,
+            hide_balances: false,
+            wrong_answer_penalty: WrongAnswerPenalty::Full,
+            pass_allows_rebuzz: false,
+            allow_co_hosts: false,
+            co_hosts_can_command: false,
+            max_players: None,
+            buzz_window_ms: 0,
+            banned_names: vec![],
+            avatar_allowlist: ["cat", "dog", "owl", "fox", "bear", "frog"]
+                .iter()
+                .map(|a| a.to_string())
+                .collect(),
+            daily_double_cap: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn penalty_applies_to_the_clue_value() {
+        assert_eq!(WrongAnswerPenalty::Full.apply(400), 400);
+        assert_eq!(WrongAnswerPenalty::None.apply(400), 0);
+        assert_eq!(WrongAnswerPenalty::Fraction(0.5).apply(400), 200);
+        assert_eq!(WrongAnswerPenalty::Fraction(0.33).apply(100), 33);
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    borrow::Cow,
+    sync::{Arc, OnceLock},
+};
+
+use serde::Deserialize;
+use warp::ws::Message;
+
+/// Wire encoding of a websocket connection, picked with `?format=` when the
+/// socket is opened. Game code always produces JSON text frames; they are
+/// re-encoded on the way out to connections that asked for msgpack.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WsQuery {
+    #[serde(default)]
+    pub format: Format,
+    /// Host sockets only: replace the current host even if it's still
+    /// connected.
+    #[serde(default)]
+    pub takeover: bool,
+    /// Board sockets only: replay the last few states before the current
+    /// one.
+    #[serde(default)]
+    pub catchup: bool,
+}
+
+/// A message queued for a client. Game code builds frames as JSON text, and
+/// a broadcast queues the same frame for every connection, so its msgpack
+/// encoding is made once and shared by all of them.
+#[derive(Clone, Debug)]
+pub struct Frame(Arc<FrameInner>);
+
+#[derive(Debug)]
+struct FrameInner {
+    msg: Message,
+    msgpack: OnceLock<Option<Vec<u8>>>,
+}
+
+impl From<Message> for Frame {
+    fn from(msg: Message) -> Frame {
+        Frame(Arc::new(FrameInner {
+            msg,
+            msgpack: OnceLock::new(),
+        }))
+    }
+}
+
+impl PartialEq for Frame {
+    fn eq(&self, other: &Frame) -> bool {
+        self.0.msg == other.0.msg
+    }
+}
+
+impl Frame {
+    pub fn text(s: impl Into<String>) -> Frame {
+        Message::text(s).into()
+    }
+
+    pub fn close() -> Frame {
+        Message::close().into()
+    }
+
+    pub fn close_with(code: impl Into<u16>, reason: impl Into<Cow<'static, str>>) -> Frame {
+        Message::close_with(code, reason).into()
+    }
+
+    pub fn to_str(&self) -> Result<&str, ()> {
+        self.0.msg.to_str()
+    }
+
+    pub fn is_close(&self) -> bool {
+        self.0.msg.is_close()
+    }
+
+    /// The msgpack encoding of a text frame, made on first use. None if the
+    /// frame isn't text or its JSON couldn't be re-encoded.
+    fn msgpack(&self) -> Option<&[u8]> {
+        self.0
+            .msgpack
+            .get_or_init(|| {
+                let txt = self.0.msg.to_str().ok()?;
+                let value: serde_json::Value = serde_json::from_str(txt).ok()?;
+                rmp_serde::to_vec_named(&value)
+                    .map_err(|e| tracing::error!("Error encoding msgpack frame: {}", e))
+                    .ok()
+            })
+            .as_deref()
+    }
+}
+
+impl Format {
+    /// Converts a queued frame into this connection's encoding.
+    pub fn encode(self, frame: &Frame) -> Message {
+        if self == Format::Msgpack {
+            if let Some(bytes) = frame.msgpack() {
+                return Message::binary(bytes);
+            }
+        }
+        frame.0.msg.clone()
+    }
+
+    /// Extracts the JSON text of an incoming frame, or None if the frame
+    /// doesn't carry a request in this connection's encoding.
+    pub fn decode(self, msg: &Message) -> Option<String> {
+        if let Ok(s) = msg.to_str() {
+            return Some(s.to_string());
+        }
+        if self == Format::Json || !msg.is_binary() {
+            return None;
+        }
+
+        let value: serde_json::Value = match rmp_serde::from_slice(msg.as_bytes()) {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("Error decoding msgpack frame: {}", e);
+                return None;
+            }
+        };
+        serde_json::to_string(&value).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msgpack_frames_round_trip_to_the_same_json() {
+        let frame = Frame::text(r#"{"message":"state","players":{"alice":{"balance":200}}}"#);
+        let encoded = Format::Msgpack.encode(&frame);
+        assert!(encoded.is_binary());
+        let decoded = Format::Msgpack.decode(&encoded).unwrap();
+        let expected: serde_json::Value = serde_json::from_str(frame.to_str().unwrap()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&decoded).unwrap(),
+            expected
+        );
+        assert_eq!(
+            Format::Json.encode(&frame),
+            Message::text(frame.to_str().unwrap())
+        );
+    }
+
+    #[test]
+    fn a_broadcast_frame_is_encoded_once() {
+        let frame = Frame::text(r#"{"message":"ready_count","ready":1}"#);
+        let copy = frame.clone();
+        let first = frame.msgpack().unwrap().as_ptr();
+        assert_eq!(copy.msgpack().unwrap().as_ptr(), first);
+    }
+
+    #[test]
+    fn closes_pass_through_unencoded() {
+        let close = Frame::close_with(1009u16, "message too big");
+        assert!(Format::Msgpack.encode(&close).is_close());
+    }
+}
@@ -1,39 +1,81 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex, Weak},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
-use warp::ws::Message;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    sync::{mpsc, RwLock},
+    time::Instant,
+};
 
+use super::{
+    format::Frame, judge_response, player::Player, sanitize::for_log, summary::RoundSummary, Clock,
+    GameConfig,
+};
 use crate::GameDefinition;
 
-use super::player::Player;
+/// Milliseconds since the Unix epoch.
+pub fn get_utc_now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
 
 pub trait Round {
     fn get_categories(&self) -> Vec<String>;
     fn get_name(&self) -> String;
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Clue {
     pub cost: i32,
     pub clue: String,
     pub response: String,
     pub is_daily_double: bool,
+    /// Collect a wager before showing the clue, like a Daily Double but
+    /// without announcing one.
+    #[serde(default)]
+    pub is_wager: bool,
+    /// An image or audio clip shown alongside the clue.
+    #[serde(default)]
+    pub media_url: Option<String>,
+    /// Makes this a multiple-choice clue: the buzzed player responds with
+    /// the index of one of these instead of free text.
+    #[serde(default)]
+    pub choices: Option<Vec<String>>,
+    /// Index into `choices` of the correct one.
+    #[serde(default)]
+    pub correct_choice: Option<usize>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Category {
     pub category: String,
     pub clues: Vec<Clue>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FinalClue {
+    pub category: String,
+    pub clue: String,
+    pub response: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(tag = "round_type")]
 pub enum RoundType {
     DefaultRound {
         categories: Vec<Category>,
         name: String,
         default_max_wager: i32,
+        /// Scales every clue cost and the max wager, so one board can be
+        /// reused at double value. Defaults to 1.
+        #[serde(default)]
+        value_multiplier: Option<i32>,
     },
     FinalRound {
         category: String,
@@ -41,6 +83,12 @@ pub enum RoundType {
         clue: String,
         response: String,
         default_max_wager: i32,
+        /// Further final-style clues played after the first one, in order.
+        #[serde(default)]
+        additional_clues: Vec<FinalClue>,
+        /// Collect a fresh wager before each clue instead of once up front.
+        #[serde(default)]
+        wager_per_clue: bool,
     },
 }
 
@@ -57,6 +105,8 @@ pub enum BareRoundType {
         categories: Vec<BareCategory>,
         name: String,
         default_max_wager: i32,
+        cols: usize,
+        rows: usize,
     },
     FinalRound {
         category: String,
@@ -66,17 +116,103 @@ pub enum BareRoundType {
 }
 
 impl RoundType {
+    /// Every clue of a final round, starting with the one given inline. Empty
+    /// for default rounds.
+    pub fn final_clues(&self) -> Vec<FinalClue> {
+        match self {
+            RoundType::DefaultRound { .. } => vec![],
+            RoundType::FinalRound {
+                category,
+                clue,
+                response,
+                additional_clues,
+                ..
+            } => {
+                let mut clues = vec![FinalClue {
+                    category: category.clone(),
+                    clue: clue.clone(),
+                    response: response.clone(),
+                }];
+                clues.extend(additional_clues.iter().cloned());
+                clues
+            }
+        }
+    }
+
+    /// Every media URL used by the round's clues, for the board to preload.
+    pub fn media_urls(&self) -> Vec<&str> {
+        match self {
+            RoundType::DefaultRound { categories, .. } => categories
+                .iter()
+                .flat_map(|c| c.clues.iter())
+                .filter_map(|clue| clue.media_url.as_deref())
+                .collect(),
+            RoundType::FinalRound { .. } => vec![],
+        }
+    }
+
+    /// How many Daily Doubles the round has.
+    pub fn daily_double_count(&self) -> usize {
+        match self {
+            RoundType::DefaultRound { categories, .. } => categories
+                .iter()
+                .flat_map(|c| c.clues.iter())
+                .filter(|clue| clue.is_daily_double)
+                .count(),
+            RoundType::FinalRound { .. } => 0,
+        }
+    }
+
+    /// What clue costs in this round are multiplied by.
+    pub fn value_multiplier(&self) -> i32 {
+        match self {
+            RoundType::DefaultRound {
+                value_multiplier, ..
+            } => value_multiplier.unwrap_or(1),
+            RoundType::FinalRound { .. } => 1,
+        }
+    }
+
+    /// Number of clues in each category, or None for final rounds.
+    pub fn grid_shape(&self) -> Option<Vec<usize>> {
+        match self {
+            RoundType::DefaultRound { categories, .. } => {
+                Some(categories.iter().map(|c| c.clues.len()).collect())
+            }
+            RoundType::FinalRound { .. } => None,
+        }
+    }
+
+    /// The (cols, rows) of a regular round's board, where rows is the length
+    /// of its longest category.
+    pub fn dimensions(&self) -> Option<(usize, usize)> {
+        match self {
+            RoundType::DefaultRound { categories, .. } => {
+                let rows = categories.iter().map(|c| c.clues.len()).max();
+                Some((categories.len(), rows.unwrap_or(0)))
+            }
+            RoundType::FinalRound { .. } => None,
+        }
+    }
+
     pub fn to_bare_round(self) -> BareRoundType {
+        let (cols, rows) = self.dimensions().unwrap_or((0, 0));
         match self {
             RoundType::DefaultRound {
                 categories,
                 name,
                 default_max_wager,
+                value_multiplier,
             } => {
+                let multiplier = value_multiplier.unwrap_or(1);
                 let categories = categories
                     .into_iter()
                     .map(|category| {
-                        let clue_costs = category.clues.into_iter().map(|clue| clue.cost).collect();
+                        let clue_costs = category
+                            .clues
+                            .into_iter()
+                            .map(|clue| clue.cost.saturating_mul(multiplier))
+                            .collect();
                         BareCategory {
                             clue_costs,
                             category: category.category,
@@ -86,7 +222,9 @@ impl RoundType {
                 BareRoundType::DefaultRound {
                     name,
                     categories,
-                    default_max_wager,
+                    default_max_wager: default_max_wager.saturating_mul(multiplier),
+                    cols,
+                    rows,
                 }
             }
             RoundType::FinalRound {
@@ -125,9 +263,38 @@ impl Round for RoundType {
 pub struct Game {
     pub rounds: Vec<RoundType>,
     pub state: State,
-    pub host_tx: Option<mpsc::UnboundedSender<Message>>,
-    pub board_tx: Option<mpsc::UnboundedSender<Message>>,
+    /// Every connected host, in the order they connected. The first one is
+    /// the lead host.
+    pub host_txs: Vec<mpsc::UnboundedSender<Frame>>,
+    pub board_tx: Option<mpsc::UnboundedSender<Frame>>,
+    pub config: GameConfig,
     pub created: u128,
+    pub last_activity: u128,
+    /// Whether a host, board or player has ever connected.
+    pub joined: bool,
+    /// The lock this game lives in, so timers can get back to it later.
+    pub handle: Weak<RwLock<Game>>,
+    /// Bumped whenever a timer is started or cancelled; a timer only fires if
+    /// the generation it was started with is still current.
+    pub timer_generation: u64,
+    /// Buzzes collected during the buzz window, with when each arrived.
+    pub pending_buzzes: Vec<(Instant, String)>,
+    pub clock: Arc<dyn Clock>,
+    /// Played between the players tied for first once the game is over.
+    pub tiebreaker: Option<Clue>,
+    /// Points won from each (row, col) of the current round.
+    pub points_won: HashMap<(usize, usize), i32>,
+    pub round_summaries: Vec<RoundSummary>,
+    /// When each player name last tried to connect, within the reconnect
+    /// window.
+    pub connect_attempts: HashMap<String, VecDeque<u128>>,
+    /// What `rng` was seeded with, so its choices can be checked afterwards.
+    pub seed: u64,
+    /// Where all of this game's randomness comes from.
+    pub rng: StdRng,
+    /// The last few states sent to the board, oldest first, so a board that
+    /// connects late can catch up.
+    pub recent_states: Mutex<VecDeque<Frame>>,
 }
 
 #[derive(Deserialize)]
@@ -137,10 +304,146 @@ pub struct BaseMessage {
 
 #[derive(Deserialize)]
 pub struct PlayerMessage {
-    pub request: String,
     pub player: String,
 }
 
+/// The most clues a single round's board can hold; `clues_shown` keeps one
+/// bit per clue.
+pub const MAX_BOARD_CELLS: usize = u64::BITS as usize;
+
+/// The most any clue, or the max wager of a round, may be worth.
+pub const MAX_CLUE_COST: i32 = 100_000;
+
+/// How many past states are kept for a late board to catch up on.
+const RECENT_STATES_CAP: usize = 8;
+
+/// Close code for a client whose message isn't a request at all.
+pub const BAD_REQUEST_CLOSE_CODE: u16 = 4400;
+
+/// Close code for a frame bigger than the server accepts.
+pub const MESSAGE_TOO_BIG_CLOSE_CODE: u16 = 1009;
+
+/// The close to send a client after an error reading from its socket, if
+/// the error was its fault: a frame over the size limit gets a 1009 so the
+/// client knows why it was dropped.
+pub fn close_for_ws_error(e: &warp::Error) -> Option<Frame> {
+    let too_big = std::error::Error::source(e)
+        .and_then(|e| e.downcast_ref::<tungstenite::Error>())
+        .is_some_and(|e| matches!(e, tungstenite::Error::Capacity(_)));
+    too_big.then(|| Frame::close_with(MESSAGE_TOO_BIG_CLOSE_CODE, "message too big"))
+}
+
+/// Close code for a player reconnecting too often; they should back off
+/// before trying again.
+pub const RECONNECT_THROTTLED_CLOSE_CODE: u16 = 4429;
+
+#[derive(Serialize)]
+struct ErrorMessage<'a> {
+    message: &'a str,
+    kind: &'a str,
+    detail: String,
+}
+
+/// Every request each kind of client can send, used to tell a client that
+/// sent another kind's request that it's in the wrong role.
+const HOST_REQUESTS: &[&str] = &[
+    "open",
+    "close",
+    "correct",
+    "player",
+    "unlock_player",
+    "set_cost",
+    "whisper",
+    "announce",
+    "reveal_next",
+    "reveal_remaining",
+    "reset_round",
+    "pass",
+    "sync",
+];
+const BOARD_REQUESTS: &[&str] = &[
+    "next_round",
+    "reset_round",
+    "shuffle_categories",
+    "response",
+    "board",
+    "sync",
+    "remove",
+    "set_player_balance",
+    "reveal",
+    "reveal_next",
+];
+const PLAYER_REQUESTS: &[&str] = &[
+    "buzz", "unbuzz", "response", "wager", "ready", "emote", "sync",
+];
+
+#[derive(Serialize)]
+struct UnhandledRequestMessage<'a> {
+    message: &'a str,
+    request: &'a str,
+}
+
+/// Tells a client its request was malformed or made no sense, with a
+/// `bad_request` error.
+pub fn send_bad_request(tx: &mpsc::UnboundedSender<Frame>, detail: String) {
+    let msg = ErrorMessage {
+        message: "error",
+        kind: "bad_request",
+        detail,
+    };
+    if let Ok(s) = serde_json::to_string(&msg) {
+        let _ = tx.send(Frame::text(s));
+    }
+}
+
+/// Tells a client its request wasn't handled: `wrong_role` if some other
+/// kind of client could have sent it, `unknown_request` if none could.
+pub fn reject_request(request: &str, tx: &mpsc::UnboundedSender<Frame>) {
+    let known = [HOST_REQUESTS, BOARD_REQUESTS, PLAYER_REQUESTS]
+        .iter()
+        .any(|requests| requests.contains(&request));
+    let msg = UnhandledRequestMessage {
+        message: if known {
+            "wrong_role"
+        } else {
+            "unknown_request"
+        },
+        request,
+    };
+    if let Ok(s) = serde_json::to_string(&msg) {
+        let _ = tx.send(Frame::text(s));
+    }
+}
+
+/// Parses the base message every request shares. If that fails the client is
+/// sent a 4400 close, and the caller should stop reading from it.
+pub fn parse_base_message(txt: &str, tx: &mpsc::UnboundedSender<Frame>) -> Option<BaseMessage> {
+    match serde_json::from_str(txt) {
+        Ok(m) => Some(m),
+        Err(e) => {
+            tracing::error!("Deserialization Error: {}", for_log(&e.to_string()));
+            let _ = tx.send(Frame::close_with(BAD_REQUEST_CLOSE_CODE, "bad request"));
+            None
+        }
+    }
+}
+
+/// Parses the body of a specific request. If that fails the client is told
+/// so with a `bad_request` error and the caller should skip the message.
+pub fn parse_request<T: DeserializeOwned>(
+    txt: &str,
+    tx: &mpsc::UnboundedSender<Frame>,
+) -> Option<T> {
+    match serde_json::from_str(txt) {
+        Ok(m) => Some(m),
+        Err(e) => {
+            tracing::warn!("Deserialization Error: {}", for_log(&e.to_string()));
+            send_bad_request(tx, e.to_string());
+            None
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct StateMessage<'a> {
     message: &'a str,
@@ -148,6 +451,38 @@ struct StateMessage<'a> {
     state: &'a State,
 }
 
+#[derive(Serialize)]
+struct FinalPendingMessage<'a> {
+    message: &'a str,
+    stage: &'a str,
+    players: Vec<&'a str>,
+}
+
+/// Sent in place of a message that couldn't be serialized, so clients can
+/// tell something went wrong instead of silently missing updates.
+fn internal_error() -> Frame {
+    Frame::text(r#"{"message":"internal_error"}"#)
+}
+
+#[derive(Serialize)]
+struct FinalResponse<'a> {
+    player: &'a str,
+    response: &'a str,
+    wager: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct FinalResponsesMessage<'a> {
+    message: &'a str,
+    responses: Vec<FinalResponse<'a>>,
+}
+
+#[derive(Serialize)]
+struct ActivePlayerMessage<'a> {
+    message: &'a str,
+    player: Option<&'a str>,
+}
+
 #[derive(Serialize)]
 struct CategoriesMessage<'a> {
     message: &'a str,
@@ -155,7 +490,52 @@ struct CategoriesMessage<'a> {
 }
 
 impl Game {
+    /// Builds a game with no connections yet. The definition must have been
+    /// validated. Without a `seed`, a random one is picked.
+    pub fn new(def: GameDefinition, clock: Arc<dyn Clock>, seed: Option<u64>) -> Arc<RwLock<Game>> {
+        let seed = seed.unwrap_or_else(rand::random);
+        let now = clock.now();
+        let mut state = State::new(&def.rounds[0]);
+        if let Some(category) = def.welcome_category {
+            state.category = category;
+        }
+        if let Some(clue) = def.welcome_clue {
+            state.clue = clue;
+        }
+        if let Some(response) = def.welcome_response {
+            state.response = response;
+        }
+
+        Arc::new_cyclic(|handle| {
+            RwLock::new(Game {
+                state,
+                host_txs: vec![],
+                board_tx: None,
+                rounds: def.rounds,
+                config: def.config,
+                created: now,
+                last_activity: now,
+                joined: false,
+                handle: handle.clone(),
+                timer_generation: 0,
+                pending_buzzes: vec![],
+                clock,
+                tiebreaker: def.tiebreaker,
+                points_won: HashMap::new(),
+                round_summaries: vec![],
+                connect_attempts: HashMap::new(),
+                seed,
+                rng: StdRng::seed_from_u64(seed),
+                recent_states: Mutex::new(VecDeque::new()),
+            })
+        })
+    }
+
     pub fn send_categories(&self) {
+        self.send_to_all(self.categories_message());
+    }
+
+    pub fn categories_message(&self) -> Frame {
         let categories = self.rounds[self.state.round_idx].get_categories();
 
         let msg = CategoriesMessage {
@@ -166,31 +546,195 @@ impl Game {
         let cat_str = match serde_json::to_string(&msg) {
             Ok(s) => s,
             Err(e) => {
-                eprintln!("Error serializing categories: {}", e);
-                return;
+                tracing::error!("Error serializing categories: {}", e);
+                return internal_error();
             }
         };
 
-        println!("{}", cat_str);
-        let msg = Message::text(cat_str);
-        self.send_to_all(msg);
+        tracing::debug!("{}", cat_str);
+        Frame::text(cat_str)
     }
 
-    fn send_to_all(&self, msg: Message) {
+    pub fn send_to_board(&self, msg: Frame) {
         if let Some(tx) = self.board_tx.as_ref() {
-            tx.send(msg.clone());
+            let _ = tx.send(msg);
+        }
+    }
+
+    pub fn send_to_host(&self, msg: Frame) {
+        for tx in &self.host_txs {
+            let _ = tx.send(msg.clone());
         }
+    }
+
+    pub fn send_to_players(&self, msg: Frame) {
         for player in self.state.players.values() {
             if let Some(tx) = player.tx.as_ref() {
-                tx.send(msg.clone());
+                let _ = tx.send(msg.clone());
             }
         }
-        if let Some(tx) = self.host_tx.as_ref() {
-            tx.send(msg);
-        }
+    }
+
+    pub fn send_to_all(&self, msg: Frame) {
+        self.send_to_board(msg.clone());
+        self.send_to_players(msg.clone());
+        self.send_to_host(msg);
     }
 
     pub fn send_state(&self) {
+        let msg = self.state_message();
+        self.record_state(&msg);
+        self.send_to_board(msg.clone());
+        self.send_to_host(msg.clone());
+        if self.balances_hidden() {
+            for player in self.state.players.values() {
+                if let Some(tx) = player.tx.as_ref() {
+                    let _ = tx.send(self.player_state_message(&player.name));
+                }
+            }
+        } else {
+            self.send_to_players(msg);
+        }
+        self.send_final_pending();
+    }
+
+    fn record_state(&self, msg: &Frame) {
+        let mut recent = match self.recent_states.lock() {
+            Ok(r) => r,
+            Err(e) => e.into_inner(),
+        };
+        if recent.back() == Some(msg) {
+            return;
+        }
+        if recent.len() == RECENT_STATES_CAP {
+            recent.pop_front();
+        }
+        recent.push_back(msg.clone());
+    }
+
+    /// Replays the states leading up to the current one to the board, so it
+    /// can animate into where the game is now.
+    pub fn send_recent_states(&self) {
+        let recent = match self.recent_states.lock() {
+            Ok(r) => r,
+            Err(e) => e.into_inner(),
+        };
+        for msg in recent.iter().take(recent.len().saturating_sub(1)) {
+            self.send_to_board(msg.clone());
+        }
+    }
+
+    /// Shows the host every final response and wager at once, before they
+    /// are judged one by one.
+    pub fn send_final_responses(&self) {
+        let mut responses: Vec<FinalResponse> = self
+            .state
+            .player_responses
+            .iter()
+            .filter_map(|(player, response)| {
+                Some(FinalResponse {
+                    player,
+                    response: response.as_deref()?,
+                    wager: self.state.wagers.get(player).copied().flatten(),
+                })
+            })
+            .collect();
+        responses.sort_unstable_by_key(|r| r.player);
+
+        let msg = FinalResponsesMessage {
+            message: "final_responses",
+            responses,
+        };
+        match serde_json::to_string(&msg) {
+            Ok(s) => self.send_to_host(Frame::text(s)),
+            Err(e) => tracing::error!("Error serializing final responses: {}", e),
+        }
+    }
+
+    fn balances_hidden(&self) -> bool {
+        self.config.hide_balances && !self.is_game_over()
+    }
+
+    /// Whether the last clue of the final round has been played and judged.
+    pub fn is_game_over(&self) -> bool {
+        self.state.round_idx + 1 == self.rounds.len()
+            && matches!(
+                self.rounds[self.state.round_idx],
+                RoundType::FinalRound { .. }
+            )
+            && self.state.state_type == StateType::Response
+            && !self.state.in_tiebreaker
+    }
+
+    /// The state as a particular player should see it, without the other
+    /// players' balances while those are hidden.
+    pub fn player_state_message(&self, name: &str) -> Frame {
+        if !self.balances_hidden() {
+            return self.state_message();
+        }
+
+        let state = StateMessage {
+            message: "state",
+            state: &self.state,
+        };
+        let mut value = match serde_json::to_value(&state) {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("Error serializing state: {}", e);
+                return internal_error();
+            }
+        };
+        if let Some(players) = value.get_mut("players").and_then(|p| p.as_object_mut()) {
+            for (player, fields) in players.iter_mut() {
+                if player != name {
+                    if let Some(fields) = fields.as_object_mut() {
+                        fields.remove("balance");
+                    }
+                }
+            }
+        }
+
+        Frame::text(value.to_string())
+    }
+
+    /// Tells the host which players still owe a final wager or response.
+    /// Players aren't sent this, so nobody sees who's lagging.
+    pub fn send_final_pending(&self) {
+        let (stage, mut players): (&str, Vec<&str>) = match self.state.state_type {
+            StateType::FinalWager => (
+                "wager",
+                self.state
+                    .wagers
+                    .iter()
+                    .filter(|(_, w)| w.is_none())
+                    .map(|(name, _)| name.as_str())
+                    .collect(),
+            ),
+            StateType::FinalClue => (
+                "response",
+                self.state
+                    .player_responses
+                    .iter()
+                    .filter(|(_, r)| r.is_none())
+                    .map(|(name, _)| name.as_str())
+                    .collect(),
+            ),
+            _ => return,
+        };
+        players.sort_unstable();
+
+        let msg = FinalPendingMessage {
+            message: "final_pending",
+            stage,
+            players,
+        };
+        match serde_json::to_string(&msg) {
+            Ok(s) => self.send_to_host(Frame::text(s)),
+            Err(e) => tracing::error!("Error serializing final_pending: {}", e),
+        }
+    }
+
+    fn state_message(&self) -> Frame {
         let state = StateMessage {
             message: "state",
             state: &self.state,
@@ -199,22 +743,79 @@ impl Game {
         let state_str = match serde_json::to_string(&state) {
             Ok(s) => s,
             Err(e) => {
-                eprintln!("Error serializing state: {}", e);
-                return;
+                tracing::error!("Error serializing state: {}", e);
+                return internal_error();
             }
         };
 
-        let state_msg = Message::text(state_str);
-        self.send_to_all(state_msg);
+        Frame::text(state_str)
+    }
+
+    /// Re-sends the current state (and optionally the categories) to a single
+    /// connection, so a client that missed a message can catch up without
+    /// reconnecting.
+    pub fn sync(&self, tx: &mpsc::UnboundedSender<Frame>, include_categories: bool) {
+        if include_categories {
+            let _ = tx.send(self.categories_message());
+        }
+        let _ = tx.send(self.state_message());
+    }
+
+    pub fn show_final_clue(&mut self) {
+        let final_clues = self.rounds[self.state.round_idx].final_clues();
+        let final_clue = match final_clues.get(self.state.final_clue_idx) {
+            Some(c) => c,
+            None => return,
+        };
+
+        self.state.state_type = StateType::FinalClue;
+        self.state.category = final_clue.category.clone();
+        self.state.clue = final_clue.clue.clone();
+        self.state.response = final_clue.response.clone();
+        self.send_state();
+    }
+
+    /// Moves on to the next clue of a multi-clue final round, returning false
+    /// if the last one has already been played.
+    fn next_final_clue(&mut self) -> bool {
+        let round = &self.rounds[self.state.round_idx];
+        let wager_per_clue = match round {
+            RoundType::FinalRound { wager_per_clue, .. } => *wager_per_clue,
+            _ => return false,
+        };
+        let final_clues = round.final_clues();
+        if self.state.final_clue_idx + 1 >= final_clues.len() {
+            return false;
+        }
+
+        self.state.final_clue_idx += 1;
+        self.state.buzzed_player = None;
+        let names: Vec<String> = self.state.players.keys().cloned().collect();
+        for name in names {
+            self.state.player_responses.insert(name.clone(), None);
+            if wager_per_clue {
+                self.state.wagers.insert(name, None);
+            }
+        }
+
+        if wager_per_clue {
+            self.state.category = final_clues[self.state.final_clue_idx].category.clone();
+            self.state.state_type = StateType::FinalWager;
+            self.send_state();
+        } else {
+            self.show_final_clue();
+        }
+        true
     }
 
     pub fn evaluate_final_responses(&mut self) {
         let mut player = None;
         let mut response = "";
 
-        let correct_response = match &self.rounds[self.state.round_idx] {
-            RoundType::FinalRound { response, .. } => response,
-            _ => return,
+        let final_clues = self.rounds[self.state.round_idx].final_clues();
+        let correct_response = match final_clues.get(self.state.final_clue_idx) {
+            Some(c) => &c.response,
+            None => return,
         };
 
         for p in self.state.players.keys() {
@@ -227,6 +828,11 @@ impl Game {
         let player = match player {
             Some(p) => p,
             None => {
+                self.state.suggested_correct = None;
+                self.state.final_reveal_player = None;
+                if self.next_final_clue() || self.start_tiebreaker() {
+                    return;
+                }
                 self.state.state_type = StateType::Response;
                 self.state.buzzed_player = None;
                 self.send_state();
@@ -235,6 +841,12 @@ impl Game {
         };
 
         self.state.state_type = StateType::Clue;
+        self.state.final_reveal_player = Some(player.clone());
+        self.state.suggested_correct = if self.config.auto_judge {
+            Some(judge_response(response, correct_response))
+        } else {
+            None
+        };
         self.state.response = format!(
             "{}'s response: {}\nCorrect response: {}",
             player, response, correct_response
@@ -248,72 +860,433 @@ impl Game {
         self.buzz(&player);
 
         self.state.player_responses.remove(&player);
-        self.state.wagers.remove(&player);
 
         self.send_state();
     }
 
+    /// The board clue in play, if the game is on one.
+    pub fn current_clue(&self) -> Option<&Clue> {
+        if self.state.in_tiebreaker {
+            return None;
+        }
+        let (row, col) = self.state.current_cell?;
+        match &self.rounds[self.state.round_idx] {
+            RoundType::DefaultRound { categories, .. } => categories.get(col)?.clues.get(row),
+            RoundType::FinalRound { .. } => None,
+        }
+    }
+
+    /// Ends the clue in play by showing its response. If that was the last
+    /// clue of the round, `on_round_complete` is acted on.
     pub fn show_response(&mut self) {
         if self.state.buzzers_open || self.state.buzzed_player.is_some() {
             return;
         }
         self.state.state_type = StateType::Response;
         self.state.responded_players.clear();
+        if self.check_round_complete() {
+            return;
+        }
         self.send_state();
     }
 
+    /// Swaps in new round content mid-game. Balances are kept, and so is
+    /// clues_shown as long as the current round's grid has the same shape.
+    pub fn replace_rounds(&mut self, rounds: Vec<RoundType>) -> Result<(), String> {
+        if matches!(
+            self.state.state_type,
+            StateType::Clue
+                | StateType::ClueLocked
+                | StateType::DailyDouble
+                | StateType::Wager
+                | StateType::FinalClue
+        ) || self.state.buzzed_player.is_some()
+        {
+            return Err("can't replace the game while a clue is active".to_string());
+        }
+        let new_round = match rounds.get(self.state.round_idx) {
+            Some(r) => r,
+            None => return Err("new game has fewer rounds than have been played".to_string()),
+        };
+
+        if new_round.grid_shape() != self.rounds[self.state.round_idx].grid_shape() {
+            self.state.clues_shown = 0;
+            self.state.cell_outcomes.clear();
+            self.points_won.clear();
+        }
+        self.state.bare_round = new_round.clone().to_bare_round();
+        self.rounds = rounds;
+
+        self.send_categories();
+        self.send_state();
+        Ok(())
+    }
+
+    /// Milliseconds since the Unix epoch, according to this game's clock.
+    pub fn now(&self) -> u128 {
+        self.clock.now()
+    }
+
+    /// Changes who is in control of the board, announcing the change so
+    /// clients don't have to diff it out of the state.
+    pub fn set_active_player(&mut self, player: Option<String>) {
+        if self.state.active_player == player {
+            return;
+        }
+        self.state.active_player = player;
+
+        let msg = ActivePlayerMessage {
+            message: "active_player",
+            player: self.state.active_player.as_deref(),
+        };
+        match serde_json::to_string(&msg) {
+            Ok(s) => self.send_to_all(Frame::text(s)),
+            Err(e) => tracing::error!("Error serializing active player: {}", e),
+        }
+    }
+
+    pub fn touch(&mut self) {
+        self.last_activity = self.now();
+    }
+
+    pub fn has_connections(&self) -> bool {
+        !self.host_txs.is_empty()
+            || self.board_tx.is_some()
+            || self.state.players.values().any(|p| p.tx.is_some())
+    }
+
+    /// Closes every connection to the game. The senders are dropped too, so
+    /// each connection's forwarding task finishes once it has passed on the
+    /// close.
     pub fn end(&mut self) {
-        self.send_to_all(Message::close());
+        self.send_to_all(Frame::close());
+        self.host_txs.clear();
+        self.board_tx = None;
+        for player in self.state.players.values_mut() {
+            player.tx = None;
+        }
     }
 }
 
+/// How an answered clue on the current board went: who answered it last,
+/// and whether they got it.
+#[derive(Serialize, Debug)]
+pub struct CellOutcome {
+    pub row: usize,
+    pub col: usize,
+    pub player: String,
+    pub correct: bool,
+}
+
 #[derive(Serialize, Debug)]
 pub struct State {
     pub state_type: StateType,
     pub buzzers_open: bool,
     pub buzzed_player: Option<String>,
+    #[serde(skip_serializing)]
+    pub buzzed_at: Option<u128>,
     pub active_player: Option<String>,
     pub responded_players: HashSet<String>,
+    pub ready_players: HashSet<String>,
+    pub in_tiebreaker: bool,
+    /// The clue in play had a wager on it (a Daily Double or `is_wager`).
+    pub wagered_clue: bool,
+    /// The (row, col) of the last clue revealed this round.
+    #[serde(skip_serializing)]
+    pub current_cell: Option<(usize, usize)>,
     pub cost: i32,
     pub category: String,
     pub clue: String,
     pub response: String,
+    pub media_url: Option<String>,
+    /// The choices of a multiple-choice clue.
+    pub choices: Option<Vec<String>>,
+    /// Which of `choices` the buzzed player picked.
+    pub chosen: Option<usize>,
+    /// Whose final response is being judged right now.
+    pub final_reveal_player: Option<String>,
+    /// The auto-judge's guess at whether the response being judged is
+    /// correct, if auto-judging is on.
+    pub suggested_correct: Option<bool>,
     pub players: HashMap<String, Player>,
-    pub clues_shown: u32,
+    pub clues_shown: u64,
+    /// Outcomes of the clues answered so far this round, so the board can
+    /// annotate their cells.
+    pub cell_outcomes: Vec<CellOutcome>,
     pub wagers: HashMap<String, Option<i32>>,
     pub player_responses: HashMap<String, Option<String>>,
     pub bare_round: BareRoundType,
     pub round_idx: usize,
+    pub final_clue_idx: usize,
+    /// When the running timer expires, in seconds since the Unix epoch.
+    pub timer_end_secs: Option<u64>,
 }
 
 #[derive(Serialize, PartialEq, Debug)]
 pub enum StateType {
     Response,
     Clue,
+    /// The host closed the buzzers on a clue nobody has buzzed in on. The
+    /// clue is still showing; reopening the buzzers goes back to `Clue`.
+    /// Clients that don't know this state can treat it as `Clue`.
+    ClueLocked,
     Board,
     DailyDouble,
+    /// A wager is being collected for a regular clue marked `is_wager`.
+    Wager,
     FinalWager,
     FinalClue,
 }
 
 impl State {
+    /// The bit for the clue at (row, col) of the current round, laid out
+    /// using that round's own number of columns.
+    pub fn clue_key(&self, row: usize, col: usize) -> u64 {
+        let cols = match self.bare_round {
+            BareRoundType::DefaultRound { cols, .. } => cols,
+            BareRoundType::FinalRound { .. } => return 0,
+        };
+        1u64.checked_shl((row * cols + col) as u32).unwrap_or(0)
+    }
+
+    pub fn is_clue_shown(&self, row: usize, col: usize) -> bool {
+        self.clues_shown & self.clue_key(row, col) != 0
+    }
+
     pub fn new(first_round: &RoundType) -> Self {
         Self {
             state_type: StateType::Board,
             buzzers_open: false,
             buzzed_player: None,
+            buzzed_at: None,
             active_player: None,
             cost: 0,
             category: "Welcome to Jeopardy!".to_string(),
             clue: "Please wait for the game to start.".to_string(),
             response: "I'm sure that'll be soon".to_string(),
+            media_url: None,
+            choices: None,
+            chosen: None,
+            final_reveal_player: None,
+            suggested_correct: None,
             players: HashMap::new(),
             responded_players: HashSet::new(),
+            ready_players: HashSet::new(),
+            in_tiebreaker: false,
+            wagered_clue: false,
+            current_cell: None,
             clues_shown: 0,
+            cell_outcomes: vec![],
             wagers: HashMap::new(),
             player_responses: HashMap::new(),
             bare_round: first_round.clone().to_bare_round(),
             round_idx: 0,
+            final_clue_idx: 0,
+            timer_end_secs: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::lib::harness::{last, test_definition, test_game, TestClients};
+
+    /// A game where alice has just got the first clue right, 200 up on bob.
+    async fn after_first_clue(config: serde_json::Value) -> TestClients {
+        let (game, _clock) = test_game(config);
+        let mut game = game.write().await;
+        let clients = game.attach_test_clients(&["alice", "bob"]);
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+        game.player_buzz("alice", Instant::now());
+        game.correct(true);
+        clients
+    }
+
+    /// The state sent after the first clue, with `players` as given.
+    fn golden_state(players: serde_json::Value) -> serde_json::Value {
+        json!({
+            "message": "state",
+            "state_type": "Response",
+            "buzzers_open": false,
+            "buzzed_player": null,
+            "active_player": "alice",
+            "responded_players": [],
+            "ready_players": [],
+            "in_tiebreaker": false,
+            "wagered_clue": false,
+            "cost": 200,
+            "category": "Rivers",
+            "clue": "Longest in Africa",
+            "response": "the Nile",
+            "media_url": null,
+            "choices": null,
+            "chosen": null,
+            "final_reveal_player": null,
+            "suggested_correct": null,
+            "players": players,
+            "clues_shown": 1,
+            "cell_outcomes": [{ "row": 0, "col": 0, "player": "alice", "correct": true }],
+            "wagers": { "alice": null, "bob": null },
+            "player_responses": { "alice": null, "bob": null },
+            "bare_round": {
+                "round_type": "DefaultRound",
+                "name": "Jeopardy",
+                "default_max_wager": 1000,
+                "cols": 2,
+                "rows": 2,
+                "categories": [
+                    { "category": "Rivers", "clue_costs": [200, 400] },
+                    { "category": "Peaks", "clue_costs": [200, 400] },
+                ],
+            },
+            "round_idx": 0,
+            "final_clue_idx": 0,
+            "timer_end_secs": null,
+        })
+    }
+
+    fn alice(balance: Option<i32>) -> serde_json::Value {
+        let mut player = json!({ "name": "alice", "color": "#e6194b", "avatar": null });
+        if let Some(balance) = balance {
+            player["balance"] = json!(balance);
+        }
+        player
+    }
+
+    fn bob(balance: Option<i32>) -> serde_json::Value {
+        let mut player = json!({ "name": "bob", "color": "#911eb4", "avatar": null });
+        if let Some(balance) = balance {
+            player["balance"] = json!(balance);
+        }
+        player
+    }
+
+    #[tokio::test]
+    async fn host_and_board_see_every_balance() {
+        let mut clients = after_first_clue(json!({ "hide_balances": true })).await;
+        let full = golden_state(json!({ "alice": alice(Some(200)), "bob": bob(Some(0)) }));
+        assert_eq!(last(&mut clients.host, "state").unwrap(), full);
+        assert_eq!(last(&mut clients.board, "state").unwrap(), full);
+    }
+
+    #[tokio::test]
+    async fn players_see_only_their_own_balance_while_hidden() {
+        let mut clients = after_first_clue(json!({ "hide_balances": true })).await;
+        let alice_view = last(clients.players.get_mut("alice").unwrap(), "state");
+        assert_eq!(
+            alice_view.unwrap(),
+            golden_state(json!({ "alice": alice(Some(200)), "bob": bob(None) }))
+        );
+        let bob_view = last(clients.players.get_mut("bob").unwrap(), "state");
+        assert_eq!(
+            bob_view.unwrap(),
+            golden_state(json!({ "alice": alice(None), "bob": bob(Some(0)) }))
+        );
+    }
+
+    #[tokio::test]
+    async fn hidden_balances_are_shown_once_the_game_is_over() {
+        let (game, _clock) = test_game(json!({ "hide_balances": true }));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice", "bob"]);
+        game.state.players.get_mut("alice").unwrap().balance = 300;
+        game.next_round();
+        game.state.state_type = StateType::Response;
+        game.send_state();
+
+        let bob_view = last(clients.players.get_mut("bob").unwrap(), "state").unwrap();
+        assert_eq!(
+            bob_view["players"],
+            json!({ "alice": alice(Some(300)), "bob": bob(Some(0)) })
+        );
+    }
+
+    #[tokio::test]
+    async fn a_player_syncing_gets_their_own_view() {
+        let (game, _clock) = test_game(json!({ "hide_balances": true }));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice", "bob"]);
+        game.state.players.get_mut("bob").unwrap().balance = 400;
+
+        let msg = game.player_state_message("alice");
+        let view: serde_json::Value = serde_json::from_str(msg.to_str().unwrap()).unwrap();
+        assert_eq!(view["players"]["alice"]["balance"], 0);
+        assert!(view["players"]["bob"].get("balance").is_none());
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        game.sync(&tx, false);
+        let full = last(&mut rx, "state").unwrap();
+        assert_eq!(full["players"]["bob"]["balance"], 400);
+    }
+
+    #[tokio::test]
+    async fn players_see_every_balance_unless_hidden() {
+        let mut clients = after_first_clue(json!({})).await;
+        let full = golden_state(json!({ "alice": alice(Some(200)), "bob": bob(Some(0)) }));
+        for rx in clients.players.values_mut() {
+            assert_eq!(last(rx, "state").unwrap(), full);
+        }
+    }
+
+    #[tokio::test]
+    async fn ending_a_game_stops_every_forwarding_task() {
+        for _ in 0..50 {
+            let (game, _clock) = test_game(json!({}));
+            let mut game = game.write().await;
+            let clients = game.attach_test_clients(&["alice", "bob"]);
+            let receivers = [clients.host, clients.board]
+                .into_iter()
+                .chain(clients.players.into_values());
+            // Stand-ins for the tasks that forward each channel to a socket.
+            let forwarders: Vec<_> = receivers
+                .map(|mut rx| {
+                    tokio::spawn(async move {
+                        let mut last = None;
+                        while let Some(msg) = rx.recv().await {
+                            last = Some(msg);
+                        }
+                        last
+                    })
+                })
+                .collect();
+
+            game.end();
+            for forwarder in forwarders {
+                let last = forwarder.await.unwrap();
+                assert!(last.is_some_and(|msg| msg.is_close()));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn replacing_with_a_new_shape_forgets_the_old_cells() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice"]);
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+        game.player_buzz("alice", Instant::now());
+        game.correct(true);
+        game.state.state_type = StateType::Board;
+
+        let same_shape = test_definition(json!({})).rounds;
+        game.replace_rounds(same_shape).unwrap();
+        assert_eq!(game.points_won.get(&(0, 0)), Some(&200));
+        assert_eq!(game.state.cell_outcomes.len(), 1);
+        assert!(game.state.is_clue_shown(0, 0));
+
+        let mut new_shape = test_definition(json!({})).rounds;
+        if let RoundType::DefaultRound { categories, .. } = &mut new_shape[0] {
+            let extra = categories[0].clues[0].clone();
+            categories[0].clues.push(extra);
         }
+        game.replace_rounds(new_shape).unwrap();
+        assert!(game.points_won.is_empty());
+        assert!(game.state.cell_outcomes.is_empty());
+        assert_eq!(game.state.clues_shown, 0);
     }
 }
@@ -0,0 +1,118 @@
+use std::{env, error::Error, fs, path::Path, process::Command};
+
+use crate::GameDefinition;
+
+const DEFAULT_GAME_PREFIX: &str = "games/";
+const GAME_PREFIX_NAME: &str = "JEOPARDY_GAME_ROOT";
+/// Base URL of the j-archive site get_game.py scrapes games from when they
+/// aren't on disk, e.g. `https://www.j-archive.com`. Missing games aren't
+/// fetched at all if this is unset.
+const J_ARCHIVE_URL_NAME: &str = "J_ARCHIVE_URL";
+
+/// Somewhere game definitions can be loaded from by ID.
+pub trait GameSource: Send + Sync {
+    fn fetch(&self, id: &str) -> Result<GameDefinition, Box<dyn Error + Send>>;
+}
+
+/// Reads games from `<JEOPARDY_GAME_ROOT>/<id>.json`, fetching missing ones
+/// with get_game.py when J_ARCHIVE_URL is set.
+pub struct FsGameSource {
+    prefix: String,
+}
+
+impl FsGameSource {
+    pub fn from_env() -> Self {
+        Self {
+            prefix: env::var(GAME_PREFIX_NAME).unwrap_or(DEFAULT_GAME_PREFIX.to_string()),
+        }
+    }
+}
+
+fn read_game(game_path: &Path) -> Result<GameDefinition, Box<dyn Error + Send>> {
+    let data = fs::read_to_string(game_path);
+    let data = match data {
+        Ok(string) => string,
+        Err(e) => return Err(Box::new(e)),
+    };
+    let res = serde_json::from_str(&data);
+    if let Err(e) = &res {
+        tracing::error!("{}", e);
+    }
+    match res {
+        Ok(def) => Ok(def),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+impl GameSource for FsGameSource {
+    fn fetch(&self, id: &str) -> Result<GameDefinition, Box<dyn Error + Send>> {
+        let game_path = format!("{}{}.json", self.prefix, id);
+        tracing::debug!("{}", game_path);
+        let game_path = Path::new(&game_path);
+
+        let game = read_game(game_path);
+        if game.is_ok() {
+            return game;
+        }
+
+        let base_url = match env::var(J_ARCHIVE_URL_NAME) {
+            Ok(url) if !url.is_empty() => url,
+            _ => return game,
+        };
+
+        let mut c = Command::new("get_game.py");
+        c.arg(id).env(J_ARCHIVE_URL_NAME, base_url);
+
+        match c.status() {
+            Ok(_) => read_game(game_path),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteGameSource;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::{error::Error, sync::Mutex};
+
+    use rusqlite::Connection;
+
+    use super::GameSource;
+    use crate::GameDefinition;
+
+    /// Reads games from a `games (id TEXT PRIMARY KEY, definition TEXT)`
+    /// table, where `definition` holds the same JSON as a game file.
+    pub struct SqliteGameSource {
+        conn: Mutex<Connection>,
+    }
+
+    impl SqliteGameSource {
+        pub fn open(path: &str) -> rusqlite::Result<Self> {
+            Ok(Self {
+                conn: Mutex::new(Connection::open(path)?),
+            })
+        }
+    }
+
+    impl GameSource for SqliteGameSource {
+        fn fetch(&self, id: &str) -> Result<GameDefinition, Box<dyn Error + Send>> {
+            let conn = match self.conn.lock() {
+                Ok(c) => c,
+                Err(e) => return Err(Box::new(std::io::Error::other(e.to_string()))),
+            };
+            let data: String =
+                match conn.query_row("SELECT definition FROM games WHERE id = ?1", [id], |row| {
+                    row.get(0)
+                }) {
+                    Ok(d) => d,
+                    Err(e) => return Err(Box::new(e)),
+                };
+            match serde_json::from_str(&data) {
+                Ok(def) => Ok(def),
+                Err(e) => Err(Box::new(e)),
+            }
+        }
+    }
+}
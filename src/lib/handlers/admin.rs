@@ -0,0 +1,75 @@
+use std::env;
+
+use warp::{http::StatusCode, reject::Reject, Filter, Rejection, Reply};
+
+const ADMIN_TOKEN_NAME: &str = "ADMIN_TOKEN";
+/// Set to "1" to leave admin routes open when ADMIN_TOKEN is unset. Meant for
+/// local development only.
+const ADMIN_ALLOW_UNAUTHENTICATED_NAME: &str = "ADMIN_ALLOW_UNAUTHENTICATED";
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+/// Only lets a request through if it carries `Authorization: Bearer <token>`
+/// matching the ADMIN_TOKEN env var. If ADMIN_TOKEN is unset every request is
+/// refused, unless ADMIN_ALLOW_UNAUTHENTICATED=1 opts out for development.
+pub fn require_admin_token() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let token = env::var(ADMIN_TOKEN_NAME).ok().filter(|t| !t.is_empty());
+    let allow_unauthenticated = env::var(ADMIN_ALLOW_UNAUTHENTICATED_NAME).as_deref() == Ok("1");
+    if token.is_none() {
+        if allow_unauthenticated {
+            tracing::warn!(
+                "{} is unset and {} is on; admin routes are unprotected",
+                ADMIN_TOKEN_NAME,
+                ADMIN_ALLOW_UNAUTHENTICATED_NAME
+            );
+        } else {
+            tracing::error!(
+                "{} is unset; admin routes will refuse every request",
+                ADMIN_TOKEN_NAME
+            );
+        }
+    }
+
+    warp::header::optional::<String>("authorization")
+        .and_then(move |auth: Option<String>| {
+            let token = token.clone();
+            async move {
+                let token = match token {
+                    Some(t) => t,
+                    None if allow_unauthenticated => return Ok(()),
+                    None => return Err(warp::reject::custom(Unauthorized)),
+                };
+                let given = auth
+                    .as_deref()
+                    .and_then(|a| a.strip_prefix("Bearer "))
+                    .unwrap_or("");
+                if tokens_match(given.as_bytes(), token.as_bytes()) {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Compares without bailing at the first differing byte, so the time taken
+/// doesn't give the token away.
+fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Turns a failed admin token check into a 401, passing other rejections on.
+pub async fn recover_unauthorized(err: Rejection) -> Result<impl Reply, Rejection> {
+    if err.find::<Unauthorized>().is_some() {
+        Ok(warp::reply::with_status(
+            "Unauthorized".to_string(),
+            StatusCode::UNAUTHORIZED,
+        ))
+    } else {
+        Err(err)
+    }
+}
@@ -1,6 +1,13 @@
-use crate::lib::{board_connected, AsyncGameList};
+use crate::lib::{board_connected, AsyncGameList, WsQuery};
 use warp::ws::WebSocket;
 
-pub fn accept_board(lobby_id: String, ws: warp::ws::Ws, games: AsyncGameList) -> impl warp::Reply {
-    ws.on_upgrade(move |ws: WebSocket| board_connected(games, lobby_id, ws))
+pub fn accept_board(
+    lobby_id: String,
+    ws: warp::ws::Ws,
+    query: WsQuery,
+    games: AsyncGameList,
+) -> impl warp::Reply {
+    ws.on_upgrade(move |ws: WebSocket| {
+        board_connected(games, lobby_id, ws, query.format, query.catchup)
+    })
 }
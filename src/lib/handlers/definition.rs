@@ -0,0 +1,57 @@
+use crate::lib::{AsyncGameList, RoundType};
+use crate::GameDefinition;
+use serde::Serialize;
+use warp::{http::StatusCode, reply::WithStatus};
+
+#[derive(Serialize)]
+struct DefinitionSnapshot<'a> {
+    rounds: &'a Vec<RoundType>,
+}
+
+/// The rounds a lobby is playing, answers included, in the same shape
+/// `update_definition` accepts.
+pub async fn get_definition(
+    lobby_id: String,
+    games: AsyncGameList,
+) -> Result<String, warp::Rejection> {
+    let game = match games.read().await.get(&lobby_id) {
+        Some(Some(g)) => g.clone(),
+        _ => return Err(warp::reject()),
+    };
+
+    let game = game.read().await;
+    let resp = DefinitionSnapshot {
+        rounds: &game.rounds,
+    };
+    match serde_json::to_string(&resp) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(warp::reject()),
+    }
+}
+
+pub async fn update_definition(
+    lobby_id: String,
+    game_def: GameDefinition,
+    games: AsyncGameList,
+) -> Result<WithStatus<String>, warp::Rejection> {
+    let game = match games.read().await.get(&lobby_id) {
+        Some(Some(g)) => g.clone(),
+        _ => return Err(warp::reject()),
+    };
+
+    if let Err(e) = game_def.validate() {
+        return Ok(warp::reply::with_status(
+            format!("Invalid game definition: {}", e),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let result = game.write().await.replace_rounds(game_def.rounds);
+    match result {
+        Ok(()) => Ok(warp::reply::with_status(
+            "Success".to_string(),
+            StatusCode::OK,
+        )),
+        Err(e) => Ok(warp::reply::with_status(e, StatusCode::CONFLICT)),
+    }
+}
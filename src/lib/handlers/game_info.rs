@@ -0,0 +1,148 @@
+use crate::lib::{game::BareRoundType, summary::RoundSummary, AsyncGameList};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Serialize)]
+struct Cell {
+    row: usize,
+    col: usize,
+}
+
+#[derive(Serialize)]
+struct BoardSnapshot<'a> {
+    #[serde(flatten)]
+    round: &'a BareRoundType,
+    clues_shown: u64,
+    revealed: Vec<Cell>,
+}
+
+pub async fn game_history(
+    lobby_id: String,
+    games: AsyncGameList,
+) -> Result<String, warp::Rejection> {
+    let games = games.read().await;
+    let game = match games.get(&lobby_id) {
+        Some(Some(g)) => g,
+        _ => return Err(warp::reject()),
+    };
+
+    let game = game.read().await;
+    let resp: HashMap<&str, &Vec<(u128, i32)>> = game
+        .state
+        .players
+        .iter()
+        .map(|(name, player)| (name.as_str(), &player.history))
+        .collect();
+
+    match serde_json::to_string(&resp) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(warp::reject()),
+    }
+}
+
+pub async fn game_board(lobby_id: String, games: AsyncGameList) -> Result<String, warp::Rejection> {
+    let games = games.read().await;
+    let game = match games.get(&lobby_id) {
+        Some(Some(g)) => g,
+        _ => return Err(warp::reject()),
+    };
+
+    let game = game.read().await;
+    let state = &game.state;
+    let mut revealed = vec![];
+    if let BareRoundType::DefaultRound { categories, .. } = &state.bare_round {
+        for (col, category) in categories.iter().enumerate() {
+            for row in 0..category.clue_costs.len() {
+                if state.is_clue_shown(row, col) {
+                    revealed.push(Cell { row, col });
+                }
+            }
+        }
+    }
+
+    let resp = BoardSnapshot {
+        round: &state.bare_round,
+        clues_shown: state.clues_shown,
+        revealed,
+    };
+    match serde_json::to_string(&resp) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(warp::reject()),
+    }
+}
+
+#[derive(Serialize)]
+struct GameSummary<'a> {
+    rounds: Vec<&'a RoundSummary>,
+    /// The game's random seed, revealed once the game is over.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+}
+
+/// How much of each category was played, for every round so far including
+/// the current one, plus the game's seed once it's over.
+pub async fn game_summary(
+    lobby_id: String,
+    games: AsyncGameList,
+) -> Result<String, warp::Rejection> {
+    let games = games.read().await;
+    let game = match games.get(&lobby_id) {
+        Some(Some(g)) => g,
+        _ => return Err(warp::reject()),
+    };
+
+    let game = game.read().await;
+    let current = game.round_summary();
+    let mut rounds: Vec<&RoundSummary> = game.round_summaries.iter().collect();
+    rounds.extend(current.as_ref());
+
+    let seed = game.is_game_over().then_some(game.seed);
+    match serde_json::to_string(&GameSummary { rounds, seed }) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(warp::reject()),
+    }
+}
+
+#[derive(Serialize)]
+struct Connections<'a> {
+    host_connected: bool,
+    host_count: usize,
+    board_connected: bool,
+    player_count: usize,
+    player_names: Vec<&'a str>,
+}
+
+/// Who is connected to a lobby right now, for debugging a board or host
+/// that isn't updating.
+pub async fn game_connections(
+    lobby_id: String,
+    games: AsyncGameList,
+) -> Result<String, warp::Rejection> {
+    let games = games.read().await;
+    let game = match games.get(&lobby_id) {
+        Some(Some(g)) => g,
+        _ => return Err(warp::reject()),
+    };
+
+    let game = game.read().await;
+    let mut player_names: Vec<&str> = game
+        .state
+        .players
+        .values()
+        .filter(|p| p.tx.is_some())
+        .map(|p| p.name.as_str())
+        .collect();
+    player_names.sort_unstable();
+
+    let resp = Connections {
+        host_connected: !game.host_txs.is_empty(),
+        host_count: game.host_txs.len(),
+        board_connected: game.board_tx.is_some(),
+        player_count: player_names.len(),
+        player_names,
+    };
+    match serde_json::to_string(&resp) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(warp::reject()),
+    }
+}
@@ -1,7 +1,15 @@
+mod admin;
 mod board;
+mod definition;
+mod game_info;
+mod players;
 mod shared;
 mod start_game;
 
+pub use admin::{recover_unauthorized, require_admin_token};
 pub use board::accept_board;
-pub use shared::{AsyncGameList, AsyncIdStore};
-pub use start_game::start_game;
+pub use definition::{get_definition, update_definition};
+pub use game_info::{game_board, game_connections, game_history, game_summary};
+pub use players::import_players;
+pub use shared::{AsyncGameList, AsyncGameSource, AsyncIdStore, AsyncIdempotencyKeys};
+pub use start_game::{start_game, StartQuery};
@@ -0,0 +1,22 @@
+use crate::lib::{player::PlayerSeat, AsyncGameList};
+use warp::{http::StatusCode, reply::WithStatus};
+
+pub async fn import_players(
+    lobby_id: String,
+    seats: Vec<PlayerSeat>,
+    games: AsyncGameList,
+) -> Result<WithStatus<String>, warp::Rejection> {
+    let game = match games.read().await.get(&lobby_id) {
+        Some(Some(g)) => g.clone(),
+        _ => return Err(warp::reject()),
+    };
+
+    let result = game.write().await.reserve_players(seats);
+    match result {
+        Ok(()) => Ok(warp::reply::with_status(
+            "Success".to_string(),
+            StatusCode::OK,
+        )),
+        Err(e) => Ok(warp::reply::with_status(e, StatusCode::BAD_REQUEST)),
+    }
+}
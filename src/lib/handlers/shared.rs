@@ -1,6 +1,11 @@
-use crate::{lib::IdStore, Game};
-use std::{collections::HashMap, sync::Arc};
+use crate::{
+    lib::{GameSource, IdStore},
+    Game,
+};
+use std::{collections::HashMap, sync::Arc, time::Instant};
 use tokio::sync::RwLock;
 
 pub type AsyncGameList = Arc<RwLock<HashMap<String, Option<Arc<RwLock<Game>>>>>>;
 pub type AsyncIdStore = Arc<RwLock<IdStore>>;
+pub type AsyncGameSource = Arc<dyn GameSource>;
+pub type AsyncIdempotencyKeys = Arc<RwLock<HashMap<String, (String, Instant)>>>;
@@ -1,22 +1,13 @@
-use crate::lib::AsyncGameList;
-use crate::Error;
+use crate::lib::{sweeper::end_if_never_joined, AsyncGameList, SystemClock};
 use crate::Game;
-use crate::GameDefinition;
-use crate::State;
-use opentelemetry::trace::{Span, SpanKind, Status};
-use opentelemetry::{global, trace::Tracer};
-use serde::Serialize;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
 use std::{
-    env, fs,
-    process::Command,
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant},
 };
-use tokio::sync::RwLock;
 use warp::reply::WithStatus;
 
-use super::AsyncIdStore;
+use super::{AsyncGameSource, AsyncIdStore, AsyncIdempotencyKeys};
 
 #[derive(Serialize)]
 struct GameCreatedMessage<'a> {
@@ -24,65 +15,96 @@ struct GameCreatedMessage<'a> {
     lobby_id: String,
 }
 
-const DEFAULT_GAME_PREFIX: &str = "games/";
-const GAME_PREFIX_NAME: &str = "JEOPARDY_GAME_ROOT";
+#[derive(Deserialize, Debug)]
+pub struct StartQuery {
+    idempotency_key: Option<String>,
+    /// Seeds the game's randomness, so its random choices can be verified
+    /// once it's over.
+    seed: Option<u64>,
+}
+
+/// How long a repeated start request with the same idempotency key keeps
+/// resolving to the lobby created by the first one.
+const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(60);
 
-#[tracing::instrument]
+#[tracing::instrument(skip(games, id_store, idempotency_keys, source))]
 pub async fn start_game(
     num: usize,
+    query: StartQuery,
     games: AsyncGameList,
     id_store: AsyncIdStore,
+    idempotency_keys: AsyncIdempotencyKeys,
+    source: AsyncGameSource,
 ) -> Result<WithStatus<String>, warp::Rejection> {
-    let id = id_store.write().await.take();
+    let key = match query.idempotency_key {
+        Some(key) => key,
+        None => return start_new_game(num, query.seed, games, id_store, source).await,
+    };
 
-    match id {
-        Some(id) => Ok(create_game(games, num, id).await),
-        None => Err(warp::reject()),
-    }
-}
+    // Hold the key map for the whole request so concurrent retries with the
+    // same key can't both miss and create two lobbies.
+    let mut keys = idempotency_keys.write().await;
+    keys.retain(|_, (_, created)| created.elapsed() < IDEMPOTENCY_KEY_TTL);
 
-fn read_game(game_path: &Path) -> Result<GameDefinition, Box<dyn Error + Send>> {
-    let data = fs::read_to_string(game_path);
-    let data = match data {
-        Ok(string) => string,
-        Err(e) => return Err(Box::new(e)),
-    };
-    let res = serde_json::from_str(&data);
-    if let Err(e) = &res {
-        println!("{}", e);
-    }
-    match res {
-        Ok(def) => Ok(def),
-        Err(e) => Err(Box::new(e)),
+    if let Some((lobby_id, _)) = keys.get(&key) {
+        if let Some(Some(_)) = games.read().await.get(lobby_id) {
+            return Ok(game_created_reply(lobby_id.clone()));
+        }
     }
-}
 
-fn read_game_or_fetch(game_name: String) -> Result<GameDefinition, Box<dyn Error + Send>> {
-    let prefix = env::var(GAME_PREFIX_NAME).unwrap_or(DEFAULT_GAME_PREFIX.to_string());
-    let game_path = format!("{}{}.json", prefix, &game_name);
-    println!("{}", game_path);
-    let game_path = Path::new(&game_path);
+    let id = match id_store.write().await.take() {
+        Some(id) => id,
+        None => return Err(warp::reject()),
+    };
 
-    let game = read_game(game_path);
-    if let Ok(game) = game {
-        return Ok(game);
+    let reply = create_game(
+        games.clone(),
+        id_store.clone(),
+        source,
+        num,
+        query.seed,
+        id.clone(),
+    )
+    .await;
+    if games.read().await.contains_key(&id) {
+        keys.insert(key, (id, Instant::now()));
     }
+    Ok(reply)
+}
 
-    let mut c = Command::new("get_game.py");
-    c.arg(game_name);
+async fn start_new_game(
+    num: usize,
+    seed: Option<u64>,
+    games: AsyncGameList,
+    id_store: AsyncIdStore,
+    source: AsyncGameSource,
+) -> Result<WithStatus<String>, warp::Rejection> {
+    let id = id_store.write().await.take();
 
-    match c.status() {
-        Ok(_) => read_game(game_path),
-        Err(e) => Err(Box::new(e)),
+    match id {
+        Some(id) => Ok(create_game(games, id_store, source, num, seed, id).await),
+        None => Err(warp::reject()),
     }
 }
 
-async fn create_game(games: AsyncGameList, num: usize, id: String) -> WithStatus<String> {
-    let game_result = read_game_or_fetch(num.to_string());
+#[tracing::instrument(skip(games, id_store, source))]
+async fn create_game(
+    games: AsyncGameList,
+    id_store: AsyncIdStore,
+    source: AsyncGameSource,
+    num: usize,
+    seed: Option<u64>,
+    lobby_id: String,
+) -> WithStatus<String> {
+    let game_result = source.fetch(&num.to_string());
     let game_def = match game_result {
         Err(e) => {
-            eprintln!("Error fetching game {}: {}", num, e);
-            eprintln!("(Couldn't ensure it exists)");
+            tracing::error!(
+                "Error fetching game {} (couldn't ensure it exists): {}",
+                num,
+                e
+            );
+            id_store.write().await.release(lobby_id);
             return warp::reply::with_status(
                 format!("Error: no game #{} found", num),
                 warp::http::StatusCode::NOT_FOUND,
@@ -90,39 +112,40 @@ async fn create_game(games: AsyncGameList, num: usize, id: String) -> WithStatus
         }
         Ok(g) => g,
     };
+    if let Err(e) = game_def.validate() {
+        tracing::error!("game {} is invalid: {}", num, e);
+        id_store.write().await.release(lobby_id);
+        return warp::reply::with_status(
+            format!("Error: game #{} is invalid: {}", num, e),
+            warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+        );
+    }
 
-    let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(d) => d.as_millis(),
-        Err(e) => {
-            return warp::reply::with_status(
-                format!(
-                    "something went wrong getting the timestamp for the new game: {}",
-                    e
-                ),
-                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-            );
-        }
-    };
+    let mut game_list = games.write().await;
 
-    let mut games = games.write().await;
+    let game = Game::new(game_def, Arc::new(SystemClock), seed);
 
-    let game = Game {
-        state: State::new(&game_def.rounds[0]),
-        host_tx: None,
-        board_tx: None,
-        rounds: game_def.rounds,
-        created: timestamp,
-    };
+    // The game is in the list, and the lock released, before the reply is
+    // built. So once a client sees the 200, /api/game/{id} and the websocket
+    // routes can already find the lobby.
+    let handle = Arc::downgrade(&game);
+    game_list.insert(lobby_id.clone(), Some(game));
+    debug_assert!(matches!(game_list.get(&lobby_id), Some(Some(_))));
+    drop(game_list);
+
+    end_if_never_joined(games, id_store, lobby_id.clone(), handle);
 
-    games.insert(id.clone(), Some(Arc::new(RwLock::new(game))));
+    tracing::info!("started game");
+    game_created_reply(lobby_id)
+}
 
+fn game_created_reply(id: String) -> WithStatus<String> {
     let msg = GameCreatedMessage {
         message: "Game created successfully",
         lobby_id: id,
     };
 
     let resp = serde_json::to_string(&msg);
-    println!("started game");
     match resp {
         Ok(s) => warp::reply::with_status(s, warp::http::StatusCode::OK),
         Err(e) => warp::reply::with_status(
@@ -131,3 +154,104 @@ async fn create_game(games: AsyncGameList, num: usize, id: String) -> WithStatus
         ),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, error::Error, io};
+
+    use serde_json::json;
+    use tokio::sync::RwLock;
+
+    use super::*;
+    use crate::lib::{harness::test_definition, GameSource, IdStore};
+    use crate::GameDefinition;
+
+    /// Serves the test definition, no definition at all, or one with no
+    /// rounds.
+    enum TestSource {
+        Valid,
+        Missing,
+        Invalid,
+    }
+
+    impl GameSource for TestSource {
+        fn fetch(&self, _id: &str) -> Result<GameDefinition, Box<dyn Error + Send>> {
+            let mut def = test_definition(json!({}));
+            match self {
+                TestSource::Valid => {}
+                TestSource::Missing => {
+                    return Err(Box::new(io::Error::from(io::ErrorKind::NotFound)))
+                }
+                TestSource::Invalid => def.rounds.clear(),
+            }
+            Ok(def)
+        }
+    }
+
+    fn stores() -> (AsyncGameList, AsyncIdStore) {
+        (
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(IdStore::new())),
+        )
+    }
+
+    async fn is_free(id_store: &AsyncIdStore, id: &str) -> bool {
+        let mut id_store = id_store.write().await;
+        std::iter::from_fn(|| id_store.take()).any(|free| free == id)
+    }
+
+    #[tokio::test]
+    async fn failed_start_releases_the_id() {
+        for (source, status) in [
+            (TestSource::Missing, warp::http::StatusCode::NOT_FOUND),
+            (
+                TestSource::Invalid,
+                warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+        ] {
+            let (games, id_store) = stores();
+            let id = id_store.write().await.take().unwrap();
+            let reply = create_game(
+                games.clone(),
+                id_store.clone(),
+                Arc::new(source),
+                1,
+                None,
+                id.clone(),
+            )
+            .await;
+
+            assert_eq!(warp::Reply::into_response(reply).status(), status);
+            assert!(games.read().await.is_empty());
+            assert!(is_free(&id_store, &id).await);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lobby_nobody_joins_is_ended_after_the_grace() {
+        let (games, id_store) = stores();
+        let id = id_store.write().await.take().unwrap();
+        let source = Arc::new(TestSource::Valid);
+        create_game(games.clone(), id_store.clone(), source, 1, None, id.clone()).await;
+        assert!(matches!(games.read().await.get(&id), Some(Some(_))));
+
+        tokio::time::sleep(Duration::from_secs(5 * 60 + 1)).await;
+        assert!(games.read().await.get(&id).is_none());
+        assert!(is_free(&id_store, &id).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lobby_someone_joined_outlives_the_grace() {
+        let (games, id_store) = stores();
+        let id = id_store.write().await.take().unwrap();
+        let source = Arc::new(TestSource::Valid);
+        create_game(games.clone(), id_store.clone(), source, 1, None, id.clone()).await;
+        if let Some(Some(game)) = games.read().await.get(&id) {
+            game.write().await.joined = true;
+        }
+
+        tokio::time::sleep(Duration::from_secs(5 * 60 + 1)).await;
+        assert!(matches!(games.read().await.get(&id), Some(Some(_))));
+        assert!(!is_free(&id_store, &id).await);
+    }
+}
@@ -0,0 +1,169 @@
+//! Test-only plumbing for driving a game without websockets: clients are
+//! plain channels whose receivers the test drains.
+
+use std::{collections::HashMap, sync::Arc};
+
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, RwLock};
+
+use super::{format::Frame, game::Game, player::PlayerSeat, MockClock};
+use crate::GameDefinition;
+
+/// What the host, board and each player have been sent.
+pub struct TestClients {
+    pub host: mpsc::UnboundedReceiver<Frame>,
+    pub board: mpsc::UnboundedReceiver<Frame>,
+    pub players: HashMap<String, mpsc::UnboundedReceiver<Frame>>,
+}
+
+/// A two-by-two round followed by a final round. The 400 clue in "Rivers"
+/// is a Daily Double.
+pub fn test_definition(config: Value) -> GameDefinition {
+    let clue = |cost: i32, clue: &str, response: &str, dd: bool| {
+        json!({
+            "cost": cost,
+            "clue": clue,
+            "response": response,
+            "is_daily_double": dd,
+        })
+    };
+    let def = json!({
+        "rounds": [
+            {
+                "round_type": "DefaultRound",
+                "name": "Jeopardy",
+                "default_max_wager": 1000,
+                "categories": [
+                    {
+                        "category": "Rivers",
+                        "clues": [
+                            clue(200, "Longest in Africa", "the Nile", false),
+                            clue(400, "Flows through Vienna", "the Danube", true),
+                        ],
+                    },
+                    {
+                        "category": "Peaks",
+                        "clues": [
+                            clue(200, "Tallest on Earth", "Everest", false),
+                            clue(400, "Tallest in Africa", "Kilimanjaro", false),
+                        ],
+                    },
+                ],
+            },
+            {
+                "round_type": "FinalRound",
+                "name": "Final Jeopardy",
+                "category": "Oceans",
+                "clue": "The deepest one",
+                "response": "the Pacific",
+                "default_max_wager": 0,
+            },
+        ],
+        "config": config,
+    });
+    serde_json::from_value(def).expect("test definition should deserialize")
+}
+
+/// A game built from [`test_definition`] on a clock that starts at zero.
+pub fn test_game(config: Value) -> (Arc<RwLock<Game>>, Arc<MockClock>) {
+    let clock = Arc::new(MockClock::new(0));
+    let game = Game::new(test_definition(config), clock.clone(), Some(0));
+    (game, clock)
+}
+
+impl Game {
+    /// Connects a host, a board and the named players to channels, as if
+    /// each had opened a socket, and returns the receiving ends.
+    pub fn attach_test_clients(&mut self, players: &[&str]) -> TestClients {
+        let (host_tx, host) = mpsc::unbounded_channel();
+        let (board_tx, board) = mpsc::unbounded_channel();
+        self.host_txs.push(host_tx);
+        self.board_tx = Some(board_tx);
+        self.joined = true;
+
+        let seats: Vec<PlayerSeat> = serde_json::from_value(json!(players
+            .iter()
+            .map(|name| json!({ "name": name }))
+            .collect::<Vec<_>>()))
+        .expect("test seats should deserialize");
+        self.reserve_players(seats)
+            .expect("test players should be valid");
+
+        let mut receivers = HashMap::new();
+        for name in players {
+            let (tx, rx) = mpsc::unbounded_channel();
+            if let Some(player) = self.state.players.get_mut(*name) {
+                player.tx = Some(tx);
+            }
+            receivers.insert(name.to_string(), rx);
+        }
+        TestClients {
+            host,
+            board,
+            players: receivers,
+        }
+    }
+}
+
+/// Every text message waiting on `rx`, parsed. Anything else (closes,
+/// pings) is skipped.
+pub fn drain(rx: &mut mpsc::UnboundedReceiver<Frame>) -> Vec<Value> {
+    let mut messages = vec![];
+    while let Ok(msg) = rx.try_recv() {
+        if let Ok(txt) = msg.to_str() {
+            messages.push(serde_json::from_str(txt).expect("messages should be JSON"));
+        }
+    }
+    messages
+}
+
+/// The last message of the given kind waiting on `rx`, if any.
+pub fn last(rx: &mut mpsc::UnboundedReceiver<Frame>, kind: &str) -> Option<Value> {
+    drain(rx)
+        .into_iter()
+        .rev()
+        .find(|msg| msg["message"] == kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::lib::game::StateType;
+
+    #[tokio::test]
+    async fn plays_a_round_through_to_final() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice", "bob"]);
+
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+        game.player_buzz("alice", Instant::now());
+        game.correct(true);
+
+        let state = last(&mut clients.board, "state").unwrap();
+        assert_eq!(state["players"]["alice"]["balance"], 200);
+        assert_eq!(state["active_player"], "alice");
+        assert_eq!(state["state_type"], "Response");
+
+        game.state.state_type = StateType::Board;
+        assert!(game.reveal(1, 0));
+        let daily_double = last(&mut clients.board, "daily_double").unwrap();
+        assert_eq!(daily_double["player"], "alice");
+
+        game.wager("alice".to_string(), 500);
+        let accepted = last(clients.players.get_mut("alice").unwrap(), "input-response");
+        assert_eq!(accepted.unwrap()["valid"], true);
+        game.correct(true);
+
+        game.next_round();
+        let state = last(&mut clients.host, "state").unwrap();
+        assert_eq!(state["state_type"], "FinalWager");
+        assert_eq!(state["players"]["alice"]["balance"], 700);
+        assert_eq!(state["players"]["bob"]["balance"], 0);
+        let bob_state = last(clients.players.get_mut("bob").unwrap(), "state").unwrap();
+        assert_eq!(bob_state, state);
+    }
+}
@@ -1,25 +1,92 @@
-use futures_util::{SinkExt, StreamExt, TryFutureExt};
-use serde::Deserialize;
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{self, UnboundedSender};
 use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::Instrument;
 use warp::ws::{Message, WebSocket};
 
 use super::{
-    game::{BaseMessage, PlayerMessage, RoundType},
+    format::{Format, Frame},
+    game::{
+        close_for_ws_error, parse_base_message, parse_request, reject_request, send_bad_request,
+        CellOutcome, PlayerMessage, RoundType, StateType, MAX_CLUE_COST,
+    },
     AsyncGameList, Game,
 };
 
 #[derive(Deserialize)]
 struct CorrectMessage {
-    request: String,
     correct: bool,
 }
 
-pub async fn host_connected(games: AsyncGameList, lobby_id: String, ws: WebSocket) {
+#[derive(Deserialize)]
+struct SetCostMessage {
+    cost: i32,
+}
+
+#[derive(Deserialize)]
+struct WhisperRequest {
+    player: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+struct WhisperMessage<'a> {
+    message: &'a str,
+    text: &'a str,
+}
+
+#[derive(Serialize)]
+struct WhisperFailedMessage<'a> {
+    message: &'a str,
+    player: &'a str,
+    reason: &'a str,
+}
+
+/// How an announcement should be styled.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+enum AnnouncementLevel {
+    #[default]
+    Info,
+    Warning,
+}
+
+#[derive(Deserialize)]
+struct AnnounceRequest {
+    text: String,
+    #[serde(default)]
+    level: AnnouncementLevel,
+}
+
+#[derive(Serialize)]
+struct AnnouncementMessage<'a> {
+    message: &'a str,
+    text: &'a str,
+    level: AnnouncementLevel,
+}
+
+/// The longest announcement the host can send, in characters.
+const MAX_ANNOUNCEMENT_CHARS: usize = 280;
+
+#[derive(Serialize)]
+struct HostRoleMessage<'a> {
+    message: &'a str,
+    lead: bool,
+}
+
+#[tracing::instrument(skip(games, ws))]
+pub async fn host_connected(
+    games: AsyncGameList,
+    lobby_id: String,
+    ws: WebSocket,
+    format: Format,
+    takeover: bool,
+) {
     let game = match games.read().await.get(&lobby_id) {
         Some(Some(g)) => g.clone(),
         _ => {
-            ws.close().await;
+            let _ = ws.close().await;
             return;
         }
     };
@@ -27,22 +94,30 @@ pub async fn host_connected(games: AsyncGameList, lobby_id: String, ws: WebSocke
     let (tx, rx) = mpsc::unbounded_channel();
     let mut rx = UnboundedReceiverStream::new(rx);
 
-    if game.write().await.host_connected(tx).is_err() {
+    if game
+        .write()
+        .await
+        .host_connected(tx.clone(), takeover)
+        .is_err()
+    {
         // There is already a host connected
-        ws_tx.send(Message::close()).await;
+        let _ = ws_tx.send(Message::close()).await;
         return;
     }
 
-    tokio::task::spawn(async move {
-        while let Some(message) = rx.next().await {
-            ws_tx
-                .send(message)
-                .unwrap_or_else(|e| {
-                    eprintln!("websocket send error: {}", e);
-                })
-                .await;
+    tokio::task::spawn(
+        async move {
+            while let Some(message) = rx.next().await {
+                if let Err(e) = ws_tx.send(format.encode(&message)).await {
+                    // Dropping rx marks this host's sender as closed, so a
+                    // new host can take over from this dead socket.
+                    tracing::error!("websocket send error: {}", e);
+                    break;
+                }
+            }
         }
-    });
+        .in_current_span(),
+    );
 
     game.write().await.send_state();
 
@@ -50,101 +125,236 @@ pub async fn host_connected(games: AsyncGameList, lobby_id: String, ws: WebSocke
         let msg = match msg {
             Ok(s) => s,
             Err(e) => {
-                eprintln!("Websocket error: {}", e);
+                tracing::error!("Websocket error: {}", e);
+                if let Some(close) = close_for_ws_error(&e) {
+                    let _ = tx.send(close);
+                }
                 break;
             }
         };
 
-        let txt = match msg.to_str() {
-            Ok(s) => s,
-            Err(_) => {
+        let txt = match format.decode(&msg) {
+            Some(s) => s,
+            None => {
                 if msg.is_close() {
                     break;
                 }
-                eprintln!("Received non-text Websocket message");
+                tracing::error!("Received non-text Websocket message");
                 continue;
             }
         };
 
-        let msg: BaseMessage = match serde_json::from_str(txt) {
-            Ok(m) => m,
-            Err(e) => {
-                eprintln!("Deserialization Error: {}", e);
-                break;
-            }
+        let msg = match parse_base_message(&txt, &tx) {
+            Some(m) => m,
+            None => break,
         };
 
+        game.write().await.touch();
+        if msg.request != "sync" && !game.read().await.can_command(&tx) {
+            tracing::warn!("ignoring {:?} from a co-host", msg.request);
+            continue;
+        }
         match msg.request.as_str() {
             "open" => game.write().await.set_buzzers_open(true),
             "close" => game.write().await.set_buzzers_open(false),
             "correct" => {
-                let msg: CorrectMessage = match serde_json::from_str(txt) {
-                    Ok(m) => m,
-                    Err(e) => {
-                        eprintln!("Deserialization Error: {}", e);
-                        break;
-                    }
+                let msg: CorrectMessage = match parse_request(&txt, &tx) {
+                    Some(m) => m,
+                    None => continue,
                 };
 
                 game.write().await.correct(msg.correct);
             }
             "player" => {
-                let msg: PlayerMessage = match serde_json::from_str(txt) {
-                    Ok(m) => m,
-                    Err(e) => {
-                        eprintln!("Deserialization Error: {}", e);
-                        break;
-                    }
+                let msg: PlayerMessage = match parse_request(&txt, &tx) {
+                    Some(m) => m,
+                    None => continue,
                 };
 
-                game.write().await.player(msg.player);
+                if !game.write().await.player(&msg.player) {
+                    send_bad_request(&tx, format!("no player named {:?}", msg.player));
+                }
             }
-            _ => {}
+            "unlock_player" => {
+                let msg: PlayerMessage = match parse_request(&txt, &tx) {
+                    Some(m) => m,
+                    None => continue,
+                };
+
+                game.write().await.unlock_player(&msg.player);
+            }
+            "set_cost" => {
+                let msg: SetCostMessage = match parse_request(&txt, &tx) {
+                    Some(m) => m,
+                    None => continue,
+                };
+
+                game.write().await.set_cost(msg.cost);
+            }
+            "whisper" => {
+                let msg: WhisperRequest = match parse_request(&txt, &tx) {
+                    Some(m) => m,
+                    None => continue,
+                };
+
+                game.read().await.whisper(&msg.player, &msg.text);
+            }
+            "announce" => {
+                let msg: AnnounceRequest = match parse_request(&txt, &tx) {
+                    Some(m) => m,
+                    None => continue,
+                };
+
+                game.read().await.announce(&msg.text, msg.level);
+            }
+            "reveal_next" => {
+                let mut game = game.write().await;
+                if game.reveal_next() {
+                    game.send_state();
+                }
+            }
+            "reveal_remaining" => game.write().await.reveal_remaining(),
+            "reset_round" => game.write().await.reset_round(),
+            "pass" => game.write().await.pass(),
+            "sync" => {
+                game.read().await.sync(&tx, false);
+            }
+            _ => reject_request(&msg.request, &tx),
         }
+        game.write().await.enforce_invariants();
     }
 
-    game.write().await.host_disconnected();
+    game.write().await.host_disconnected(&tx);
 }
 
 impl Game {
-    fn set_buzzers_open(&mut self, open: bool) {
+    pub fn set_buzzers_open(&mut self, open: bool) {
         self.state.buzzers_open = open;
+        match self.state.state_type {
+            StateType::Clue if !open && self.state.buzzed_player.is_none() => {
+                self.state.state_type = StateType::ClueLocked;
+            }
+            StateType::ClueLocked if open => self.state.state_type = StateType::Clue,
+            _ => {}
+        }
         self.send_state();
     }
 
-    fn host_connected(&mut self, tx: UnboundedSender<Message>) -> Result<(), ()> {
-        if self.host_tx.is_some() {
-            Err(())
-        } else {
-            self.host_tx = Some(tx);
-            self.send_state();
-            Ok(())
+    /// Registers a new host connection. With co-hosts off, an existing host
+    /// is only kept if its socket is still alive and `takeover` wasn't
+    /// requested. A takeover replaces every host.
+    fn host_connected(&mut self, tx: UnboundedSender<Frame>, takeover: bool) -> Result<(), ()> {
+        self.host_txs.retain(|host_tx| !host_tx.is_closed());
+        if takeover {
+            if !self.host_txs.is_empty() {
+                tracing::info!("replacing existing host connections");
+            }
+            for old_tx in self.host_txs.drain(..) {
+                let _ = old_tx.send(Frame::close());
+            }
+        } else if !self.host_txs.is_empty() && !self.config.allow_co_hosts {
+            return Err(());
         }
+        self.host_txs.push(tx);
+        self.joined = true;
+        self.send_host_roles();
+        self.send_state();
+        self.send_round_meta();
+        Ok(())
     }
-    fn host_disconnected(&mut self) {
-        if let Some(tx) = &self.host_tx {
-            tx.send(Message::close());
+
+    /// Drops a host connection. If it was the lead host, the next host to
+    /// have connected takes over.
+    fn host_disconnected(&mut self, tx: &UnboundedSender<Frame>) {
+        let idx = match self.host_txs.iter().position(|h| h.same_channel(tx)) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let host_tx = self.host_txs.remove(idx);
+        let _ = host_tx.send(Frame::close());
+        if idx == 0 && !self.host_txs.is_empty() {
+            tracing::info!("promoting the next host to lead");
+            self.send_host_roles();
         }
-        self.host_tx = None;
     }
 
-    fn correct(&mut self, correct: bool) {
-        if let Some(player) = &self.state.buzzed_player {
+    /// Whether commands from this host connection should be carried out.
+    fn can_command(&self, tx: &UnboundedSender<Frame>) -> bool {
+        match self.host_txs.iter().position(|h| h.same_channel(tx)) {
+            Some(0) => true,
+            Some(_) => self.config.co_hosts_can_command,
+            None => false,
+        }
+    }
+
+    /// Tells each host whether they're the lead.
+    fn send_host_roles(&self) {
+        for (idx, tx) in self.host_txs.iter().enumerate() {
+            let msg = HostRoleMessage {
+                message: "host_role",
+                lead: idx == 0,
+            };
+            match serde_json::to_string(&msg) {
+                Ok(s) => {
+                    let _ = tx.send(Frame::text(s));
+                }
+                Err(e) => tracing::error!("Error serializing host role: {}", e),
+            }
+        }
+    }
+
+    pub fn correct(&mut self, correct: bool) {
+        self.cancel_timer();
+        if self.state.in_tiebreaker {
+            self.judge_tiebreaker(correct);
+            return;
+        }
+        if let Some(player) = self.state.buzzed_player.clone() {
+            let now = self.now();
+            let penalty = match self.rounds[self.state.round_idx] {
+                RoundType::FinalRound { .. } => self.state.cost,
+                RoundType::DefaultRound { .. } => {
+                    self.config.wrong_answer_penalty.apply(self.state.cost)
+                }
+            };
             self.state.players.entry(player.clone()).and_modify(|p| {
-                p.balance += if correct {
-                    self.state.cost
-                } else {
-                    -self.state.cost
-                };
+                p.set_balance(
+                    if correct {
+                        p.balance.saturating_add(self.state.cost)
+                    } else {
+                        p.balance.saturating_sub(penalty)
+                    },
+                    now,
+                );
             });
 
+            if correct {
+                self.record_points_won(self.state.cost);
+            }
+
             if let RoundType::FinalRound { .. } = self.rounds[self.state.round_idx] {
                 self.evaluate_final_responses();
                 self.send_state();
                 return;
             }
+            self.state.chosen = None;
+            self.state.suggested_correct = None;
+            self.record_cell_outcome(&player, correct);
 
-            if correct || self.state.responded_players.len() == self.state.players.keys().len() {
+            if self.state.wagered_clue {
+                // Nobody else can answer a wagered clue, and whoever wagered
+                // keeps control of the board whether they got it or not.
+                self.set_active_player(Some(player));
+                self.state.buzzed_player = None;
+                self.state.buzzers_open = false;
+                self.show_response();
+            } else if correct
+                || self.state.responded_players.len() == self.state.players.keys().len()
+            {
+                if correct {
+                    // Whoever gets a clue right picks the next one.
+                    self.set_active_player(Some(player));
+                }
                 self.state.buzzed_player = None;
                 self.state.buzzers_open = false;
                 self.show_response();
@@ -155,11 +365,398 @@ impl Game {
             }
         } else {
             self.state.buzzers_open = true;
+            if self.state.state_type == StateType::ClueLocked {
+                self.state.state_type = StateType::Clue;
+            }
         }
     }
 
-    fn player(&mut self, player: String) {
-        self.state.active_player = Some(player);
+    /// Notes how the clue in play went for this player, replacing whatever
+    /// was noted for it before.
+    fn record_cell_outcome(&mut self, player: &str, correct: bool) {
+        let (row, col) = match self.state.current_cell {
+            Some(cell) => cell,
+            None => return,
+        };
+        let outcomes = &mut self.state.cell_outcomes;
+        outcomes.retain(|o| (o.row, o.col) != (row, col));
+        outcomes.push(CellOutcome {
+            row,
+            col,
+            player: player.to_string(),
+            correct,
+        });
+    }
+
+    /// Lets a player who already answered the clue in play buzz in again,
+    /// e.g. after they were wrongly judged incorrect. If the buzzers are
+    /// still open to the others they stay open; otherwise the clue is
+    /// reopened to that player alone, even once its response is showing.
+    fn unlock_player(&mut self, player: &str) {
+        if !matches!(
+            self.state.state_type,
+            StateType::Clue | StateType::ClueLocked | StateType::Response
+        ) || self.state.current_cell.is_none()
+            || self.state.wagered_clue
+            || self.state.in_tiebreaker
+            || self.state.buzzed_player.is_some()
+        {
+            tracing::warn!("can't unlock a player with no clue to reopen");
+            return;
+        }
+        if let RoundType::FinalRound { .. } = self.rounds[self.state.round_idx] {
+            return;
+        }
+        if !self.state.players.contains_key(player) {
+            return;
+        }
+
+        if self.state.state_type == StateType::Clue && self.state.buzzers_open {
+            if !self.state.responded_players.remove(player) {
+                return;
+            }
+        } else {
+            // Everyone else counts as having answered, so only this player
+            // can buzz and the clue closes again once they're judged.
+            self.state.responded_players = self
+                .state
+                .players
+                .keys()
+                .filter(|name| *name != player)
+                .cloned()
+                .collect();
+        }
+
+        self.state.buzzers_open = true;
+        self.state.state_type = StateType::Clue;
         self.send_state();
     }
+
+    /// Moves on from a buzzed player who didn't answer, without touching
+    /// their balance. Wagered and final clues have to be judged.
+    fn pass(&mut self) {
+        if self.state.wagered_clue || self.state.in_tiebreaker {
+            return;
+        }
+        if let RoundType::FinalRound { .. } = self.rounds[self.state.round_idx] {
+            return;
+        }
+        let player = match self.state.buzzed_player.take() {
+            Some(p) => p,
+            None => return,
+        };
+
+        self.cancel_timer();
+        if self.config.pass_allows_rebuzz {
+            self.state.responded_players.remove(&player);
+        }
+        if self.state.responded_players.len() == self.state.players.len() {
+            self.state.buzzers_open = false;
+            self.show_response();
+        } else {
+            self.state.buzzers_open = true;
+            self.send_state();
+        }
+    }
+
+    /// Marks every clue left in the current round as shown, without scoring
+    /// any of them, and goes back to the board.
+    fn reveal_remaining(&mut self) {
+        let categories = match &self.rounds[self.state.round_idx] {
+            RoundType::DefaultRound { categories, .. } => categories,
+            RoundType::FinalRound { .. } => return,
+        };
+
+        for (col, category) in categories.iter().enumerate() {
+            for row in 0..category.clues.len() {
+                self.state.clues_shown |= self.state.clue_key(row, col);
+            }
+        }
+        self.drop_clue_in_play();
+        if !self.check_round_complete() {
+            self.send_state();
+        }
+    }
+
+    /// Sends a private message to one player, telling the host if they
+    /// can't be reached.
+    fn whisper(&self, player: &str, text: &str) {
+        let reason = match self.state.players.get(player).map(|p| &p.tx) {
+            Some(Some(tx)) => {
+                let msg = WhisperMessage {
+                    message: "whisper",
+                    text,
+                };
+                if let Ok(s) = serde_json::to_string(&msg) {
+                    let _ = tx.send(Frame::text(s));
+                }
+                return;
+            }
+            Some(None) => "player isn't connected",
+            None => "no such player",
+        };
+
+        let msg = WhisperFailedMessage {
+            message: "whisper_failed",
+            player,
+            reason,
+        };
+        if let Ok(s) = serde_json::to_string(&msg) {
+            self.send_to_host(Frame::text(s));
+        }
+    }
+
+    /// Shows a banner from the host on every client. Control characters are
+    /// dropped, and empty or overlong announcements are ignored.
+    fn announce(&self, text: &str, level: AnnouncementLevel) {
+        let text: String = text.chars().filter(|c| !c.is_control()).collect();
+        let text = text.trim();
+        if text.is_empty() || text.chars().count() > MAX_ANNOUNCEMENT_CHARS {
+            tracing::warn!("ignoring announcement of {} chars", text.chars().count());
+            return;
+        }
+
+        let msg = AnnouncementMessage {
+            message: "announcement",
+            text,
+            level,
+        };
+        match serde_json::to_string(&msg) {
+            Ok(s) => self.send_to_all(Frame::text(s)),
+            Err(e) => tracing::error!("Error serializing announcement: {}", e),
+        }
+    }
+
+    /// Corrects what the clue in play is worth, before it's judged.
+    fn set_cost(&mut self, cost: i32) {
+        if !matches!(
+            self.state.state_type,
+            StateType::Clue | StateType::ClueLocked | StateType::DailyDouble | StateType::Wager
+        ) {
+            tracing::warn!("can't set the cost with no clue in play");
+            return;
+        }
+        if !(0..=MAX_CLUE_COST).contains(&cost) {
+            tracing::warn!("rejecting clue cost {}", cost);
+            return;
+        }
+        self.state.cost = cost;
+        self.send_state();
+    }
+
+    /// Gives a player control of the board. Returns false, changing
+    /// nothing, if there's no such player.
+    fn player(&mut self, player: &str) -> bool {
+        if !self.state.players.contains_key(player) {
+            return false;
+        }
+        self.set_active_player(Some(player.to_string()));
+        self.send_state();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Instant;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::lib::harness::{last, test_game};
+
+    #[tokio::test]
+    async fn scores_saturate_instead_of_overflowing() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice", "bob"]);
+        game.state.players.get_mut("alice").unwrap().balance = i32::MAX - 100;
+        game.state.players.get_mut("bob").unwrap().balance = i32::MIN + 100;
+
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+        game.player_buzz("bob", Instant::now());
+        game.correct(false);
+        assert_eq!(game.state.players["bob"].balance, i32::MIN);
+        game.player_buzz("alice", Instant::now());
+        game.correct(true);
+        assert_eq!(game.state.players["alice"].balance, i32::MAX);
+
+        game.state.state_type = StateType::Board;
+        assert!(game.reveal(1, 0));
+        game.wager("alice".to_string(), i32::MAX);
+        game.correct(true);
+        assert_eq!(game.state.players["alice"].balance, i32::MAX);
+    }
+
+    #[tokio::test]
+    async fn control_passes_to_whoever_answers_correctly() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice", "bob"]);
+        game.set_active_player(Some("alice".to_string()));
+
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+        game.player_buzz("alice", Instant::now());
+        game.correct(false);
+        assert_eq!(game.state.active_player.as_deref(), Some("alice"));
+        game.player_buzz("bob", Instant::now());
+        game.correct(true);
+        assert_eq!(game.state.active_player.as_deref(), Some("bob"));
+
+        game.state.state_type = StateType::Board;
+        assert!(game.reveal(0, 1));
+        game.set_buzzers_open(true);
+        game.player_buzz("bob", Instant::now());
+        game.correct(false);
+        game.player_buzz("alice", Instant::now());
+        game.correct(false);
+        assert_eq!(game.state.active_player.as_deref(), Some("bob"));
+    }
+
+    #[tokio::test]
+    async fn next_host_is_promoted_when_the_lead_leaves() {
+        let (game, _clock) = test_game(json!({ "allow_co_hosts": true }));
+        let mut game = game.write().await;
+        let (lead, _lead_rx) = mpsc::unbounded_channel();
+        let (co_host, mut co_host_rx) = mpsc::unbounded_channel();
+        assert!(game.host_connected(lead.clone(), false).is_ok());
+        assert!(game.host_connected(co_host.clone(), false).is_ok());
+        assert!(game.can_command(&lead));
+        assert!(!game.can_command(&co_host));
+        assert_eq!(last(&mut co_host_rx, "host_role").unwrap()["lead"], false);
+
+        game.host_disconnected(&lead);
+        assert!(game.can_command(&co_host));
+        assert_eq!(last(&mut co_host_rx, "host_role").unwrap()["lead"], true);
+    }
+
+    #[tokio::test]
+    async fn second_host_is_refused_without_co_hosts() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let (first, _first_rx) = mpsc::unbounded_channel();
+        let (second, _second_rx) = mpsc::unbounded_channel();
+        assert!(game.host_connected(first.clone(), false).is_ok());
+        assert!(game.host_connected(second.clone(), false).is_err());
+
+        assert!(game.host_connected(second.clone(), true).is_ok());
+        assert!(game.can_command(&second));
+        assert!(!game.can_command(&first));
+    }
+
+    #[tokio::test]
+    async fn unlocked_player_can_buzz_again_while_others_still_can() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice", "bob", "carol"]);
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+        game.player_buzz("alice", Instant::now());
+        game.correct(false);
+
+        game.player_buzz("alice", Instant::now());
+        assert_eq!(game.state.buzzed_player, None);
+
+        game.unlock_player("alice");
+        assert!(game.state.responded_players.is_empty());
+        game.player_buzz("bob", Instant::now());
+        assert_eq!(game.state.buzzed_player.as_deref(), Some("bob"));
+    }
+
+    #[tokio::test]
+    async fn unlocking_after_the_response_reopens_for_that_player_only() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice", "bob", "carol"]);
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+        for player in ["alice", "bob", "carol"] {
+            game.player_buzz(player, Instant::now());
+            game.correct(false);
+        }
+        assert_eq!(game.state.state_type, StateType::Response);
+
+        game.unlock_player("bob");
+        assert_eq!(game.state.state_type, StateType::Clue);
+        game.player_buzz("alice", Instant::now());
+        game.player_buzz("carol", Instant::now());
+        assert_eq!(game.state.buzzed_player, None);
+        game.player_buzz("bob", Instant::now());
+        assert_eq!(game.state.buzzed_player.as_deref(), Some("bob"));
+
+        game.correct(false);
+        assert_eq!(game.state.state_type, StateType::Response);
+        assert_eq!(game.state.players["bob"].balance, -400);
+    }
+
+    #[tokio::test]
+    async fn unlocking_a_locked_clue_reopens_for_that_player_only() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice", "bob"]);
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+        game.player_buzz("alice", Instant::now());
+        game.correct(false);
+        game.set_buzzers_open(false);
+        assert_eq!(game.state.state_type, StateType::ClueLocked);
+
+        game.unlock_player("alice");
+        game.player_buzz("bob", Instant::now());
+        assert_eq!(game.state.buzzed_player, None);
+        game.player_buzz("alice", Instant::now());
+        assert_eq!(game.state.buzzed_player.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn nothing_to_unlock_on_the_board() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice"]);
+        game.unlock_player("alice");
+        assert_eq!(game.state.state_type, StateType::Board);
+        assert!(!game.state.buzzers_open);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn revealing_the_rest_mid_clue_stops_its_timer() {
+        let (game, _clock) = test_game(json!({ "answer_timeout_secs": 5 }));
+        let _clients = {
+            let mut game = game.write().await;
+            let clients = game.attach_test_clients(&["alice", "bob"]);
+            assert!(game.reveal(0, 0));
+            game.set_buzzers_open(true);
+            game.player_buzz("alice", Instant::now());
+            assert!(game.state.timer_end_secs.is_some());
+
+            game.reveal_remaining();
+            assert_eq!(game.state.state_type, StateType::Board);
+            assert_eq!(game.state.timer_end_secs, None);
+            assert_eq!(game.state.current_cell, None);
+            assert_eq!(game.state.buzzed_player, None);
+            assert!(game.state.is_clue_shown(1, 1));
+            clients
+        };
+
+        tokio::time::sleep(std::time::Duration::from_secs(6)).await;
+        let game = game.read().await;
+        assert_eq!(game.state.players["alice"].balance, 0);
+        assert_eq!(game.state.state_type, StateType::Board);
+    }
+
+    #[tokio::test]
+    async fn revealing_the_rest_mid_daily_double_forgets_the_wager() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice"]);
+        assert!(game.reveal(1, 0));
+        game.wager("alice".to_string(), 500);
+        assert!(game.state.wagered_clue);
+
+        game.reveal_remaining();
+        assert!(!game.state.wagered_clue);
+        assert!(game.state.responded_players.is_empty());
+    }
 }
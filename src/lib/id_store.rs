@@ -5,6 +5,12 @@ pub struct IdStore {
     unused_ids: Vec<String>,
 }
 
+impl Default for IdStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl IdStore {
     pub fn new() -> Self {
         let prefixes = vec![
@@ -37,12 +43,11 @@ impl IdStore {
 
         let mut ids: Vec<String> = prefixes
             .into_iter()
-            .map(|prefix| {
+            .flat_map(|prefix| {
                 suffixes
                     .iter()
                     .map(move |suffix| format!("{prefix}-{suffix}"))
             })
-            .flatten()
             .collect();
 
         ids.shuffle(&mut rand::thread_rng());
@@ -53,4 +58,10 @@ impl IdStore {
     pub fn take(&mut self) -> Option<String> {
         self.unused_ids.pop()
     }
+
+    /// Returns an ID to the pool. It goes to the back of the queue so it isn't
+    /// handed out again straight away.
+    pub fn release(&mut self, id: String) {
+        self.unused_ids.insert(0, id);
+    }
 }
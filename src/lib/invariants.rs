@@ -0,0 +1,67 @@
+use super::{game::StateType, Game, RoundType};
+
+impl Game {
+    /// Checks the state for things that should never happen, describing the
+    /// first one found.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        if self.state.round_idx >= self.rounds.len() {
+            return Err(format!(
+                "round_idx {} is past the last of {} rounds",
+                self.state.round_idx,
+                self.rounds.len()
+            ));
+        }
+        if let Some(p) = &self.state.buzzed_player {
+            if !self.state.players.contains_key(p) {
+                return Err(format!("buzzed player {} isn't in the game", p));
+            }
+        }
+        if let Some(p) = &self.state.active_player {
+            if !self.state.players.contains_key(p) {
+                return Err(format!("active player {} isn't in the game", p));
+            }
+        }
+        let round = &self.rounds[self.state.round_idx];
+        if let RoundType::FinalRound { .. } = round {
+            if self.state.final_clue_idx >= round.final_clues().len() {
+                return Err(format!(
+                    "final_clue_idx {} is past the last final clue",
+                    self.state.final_clue_idx
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Run after every request. If the request left the game in a state it
+    /// should never be in, logs why and falls back to the board of the
+    /// current round rather than carrying on from there.
+    pub fn enforce_invariants(&mut self) {
+        let violation = match self.check_invariants() {
+            Ok(()) => return,
+            Err(e) => e,
+        };
+        tracing::error!(
+            "game state is inconsistent, resetting to the board: {}",
+            violation
+        );
+
+        self.cancel_timer();
+        self.state.round_idx = self.state.round_idx.min(self.rounds.len() - 1);
+        self.state.final_clue_idx = 0;
+        self.state.buzzed_player = None;
+        self.state.buzzers_open = false;
+        self.state.responded_players.clear();
+        self.state.in_tiebreaker = false;
+        if let Some(p) = &self.state.active_player {
+            if !self.state.players.contains_key(p) {
+                self.set_active_player(None);
+            }
+        }
+        self.state.state_type = match self.rounds[self.state.round_idx] {
+            RoundType::DefaultRound { .. } => StateType::Board,
+            RoundType::FinalRound { .. } => StateType::FinalWager,
+        };
+        self.send_state();
+    }
+}
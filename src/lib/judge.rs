@@ -0,0 +1,125 @@
+/// Question-style lead-ins stripped before comparing.
+const PREFIXES: [&str; 12] = [
+    "what is ",
+    "what are ",
+    "what was ",
+    "what were ",
+    "who is ",
+    "who are ",
+    "who was ",
+    "who were ",
+    "where is ",
+    "where are ",
+    "when is ",
+    "when was ",
+];
+const ARTICLES: [&str; 3] = ["the ", "an ", "a "];
+
+/// Lowercases, drops punctuation, question lead-ins and leading articles,
+/// and collapses whitespace.
+fn normalize(response: &str) -> String {
+    let mut s: String = response
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    s.push(' ');
+
+    for prefix in PREFIXES {
+        if let Some(rest) = s.strip_prefix(prefix) {
+            s = rest.to_string();
+            break;
+        }
+    }
+    for article in ARTICLES {
+        if let Some(rest) = s.strip_prefix(article) {
+            s = rest.to_string();
+            break;
+        }
+    }
+    s.trim_end().to_string()
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Whether a submitted response is close enough to the expected one to count
+/// as correct: equal once normalized, give or take a typo for every five
+/// characters.
+pub fn judge_response(submitted: &str, expected: &str) -> bool {
+    let submitted: Vec<char> = normalize(submitted).chars().collect();
+    let expected: Vec<char> = normalize(expected).chars().collect();
+    if submitted.is_empty() || expected.is_empty() {
+        return false;
+    }
+    levenshtein(&submitted, &expected) <= expected.len() / 5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_strips_lead_ins_and_articles() {
+        assert_eq!(normalize("What were the Beatles?"), "beatles");
+        assert_eq!(normalize("where are  Alps"), "alps");
+        assert_eq!(normalize("Danube"), "danube");
+    }
+
+    #[test]
+    fn exact_responses_are_accepted() {
+        assert!(judge_response("the Nile", "the Nile"));
+        assert!(judge_response("NILE!", "the Nile"));
+        assert!(judge_response("Mount   Everest", "mount everest"));
+    }
+
+    #[test]
+    fn question_lead_ins_are_ignored() {
+        assert!(judge_response("What is the Danube?", "the Danube"));
+        assert!(judge_response("who are the Beatles", "The Beatles"));
+        assert!(judge_response("Where is Kilimanjaro?", "Kilimanjaro"));
+        assert!(!judge_response("What is", "the Danube"));
+    }
+
+    #[test]
+    fn typos_are_allowed_one_per_five_characters() {
+        // "kilimanjaro" is eleven characters, so two edits are allowed.
+        assert!(judge_response("Kilimanjro", "Kilimanjaro"));
+        assert!(judge_response("Kilimanjor", "Kilimanjaro"));
+        assert!(!judge_response("Klimanjor", "Kilimanjaro"));
+        // "nile" is too short for any.
+        assert!(!judge_response("Nole", "the Nile"));
+    }
+
+    #[test]
+    fn empty_responses_are_rejected() {
+        assert!(!judge_response("", "the Nile"));
+        assert!(!judge_response("?!...", "the Nile"));
+        assert!(!judge_response("   ", "the Nile"));
+        assert!(!judge_response("the", "the Nile"));
+        assert!(!judge_response("anything", "?!"));
+    }
+
+    #[test]
+    fn levenshtein_counts_single_character_edits() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(levenshtein(&chars("nile"), &chars("nile")), 0);
+        assert_eq!(levenshtein(&chars(""), &chars("nile")), 4);
+        assert_eq!(levenshtein(&chars("nile"), &chars("")), 4);
+        assert_eq!(levenshtein(&chars("kitten"), &chars("sitting")), 3);
+        assert_eq!(levenshtein(&chars("danube"), &chars("danub")), 1);
+    }
+}
@@ -1,14 +1,35 @@
 pub mod handlers;
 
 mod board;
+mod clock;
+mod config;
+mod format;
 mod game;
+mod game_source;
+#[cfg(test)]
+pub mod harness;
 mod host;
 mod id_store;
+mod invariants;
+mod judge;
 mod player;
+mod sanitize;
+mod summary;
+mod sweeper;
+mod tiebreaker;
+mod timer;
 
 pub use board::board_connected;
-pub use game::{Game, Round, RoundType, State};
-pub use handlers::{accept_board, AsyncGameList, AsyncIdStore};
+pub use clock::{Clock, MockClock, SystemClock};
+pub use config::{GameConfig, RoundComplete, WrongAnswerPenalty};
+pub use format::{Format, WsQuery};
+pub use game::{Clue, Game, Round, RoundType, State, MAX_BOARD_CELLS, MAX_CLUE_COST};
+#[cfg(feature = "sqlite")]
+pub use game_source::SqliteGameSource;
+pub use game_source::{FsGameSource, GameSource};
+pub use handlers::{accept_board, AsyncGameList, AsyncIdStore, AsyncIdempotencyKeys};
 pub use host::host_connected;
 pub use id_store::IdStore;
-pub use player::{player_connected, Player};
+pub use judge::judge_response;
+pub use player::{player_connected, Player, MIN_WAGER};
+pub use sweeper::sweep_lobbies;
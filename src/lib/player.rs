@@ -1,92 +1,358 @@
-use std::{cmp, default, sync::Arc};
+use std::{cmp, collections::HashSet, env};
 
 use futures_util::{SinkExt, StreamExt, TryFutureExt};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc, RwLock};
+use tokio::{sync::mpsc, time::Instant};
 use warp::ws::{Message, WebSocket};
 
 use super::{
-    game::{BaseMessage, Game, Round, RoundType, StateType},
+    format::{Format, Frame},
+    game::{
+        close_for_ws_error, parse_base_message, parse_request, reject_request, Clue, Game,
+        RoundType, StateType, BAD_REQUEST_CLOSE_CODE, RECONNECT_THROTTLED_CLOSE_CODE,
+    },
+    sanitize::for_log,
     AsyncGameList,
 };
 use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::Instrument;
 
 #[derive(Serialize, Debug)]
 pub struct Player {
     pub name: String,
     #[serde(skip_serializing)]
-    pub tx: Option<mpsc::UnboundedSender<Message>>,
+    pub tx: Option<mpsc::UnboundedSender<Frame>>,
     pub balance: i32,
+    /// A `#rrggbb` color for the player, picked from their name unless
+    /// they chose one.
+    pub color: String,
+    /// One of the game's allowed avatars, if they chose one.
+    pub avatar: Option<String>,
     #[serde(skip_serializing)]
     pub did_auth: bool,
+    /// Every balance the player has held, as (timestamp, balance) pairs.
+    #[serde(skip_serializing)]
+    pub history: Vec<(u128, i32)>,
+    #[serde(skip_serializing)]
+    pub last_emote: Option<u128>,
+}
+
+impl Player {
+    pub fn set_balance(&mut self, balance: i32, at: u128) {
+        self.balance = balance;
+        self.history.push((at, balance));
+    }
+}
+
+/// A seat reserved ahead of time for a named player.
+#[derive(Deserialize)]
+pub struct PlayerSeat {
+    name: String,
+    #[serde(default)]
+    balance: i32,
 }
 
 #[derive(Deserialize)]
 struct ConnectMessage {
-    request: String,
     name: String,
-    password: Option<String>,
+    color: Option<String>,
+    avatar: Option<String>,
+}
+
+/// Colors handed out to players who don't pick their own.
+const DEFAULT_COLORS: &[&str] = &[
+    "#e6194b", "#3cb44b", "#ffe119", "#4363d8", "#f58231", "#911eb4", "#42d4f4", "#f032e6",
+];
+
+/// The same name always gets the same color.
+fn default_color(name: &str) -> String {
+    let hash = name
+        .bytes()
+        .fold(0u32, |h, b| h.wrapping_mul(31).wrapping_add(u32::from(b)));
+    DEFAULT_COLORS[hash as usize % DEFAULT_COLORS.len()].to_string()
+}
+
+fn is_hex_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
 }
 
 #[derive(Deserialize)]
 struct WagerMessage {
-    request: String,
     amount: i32,
 }
 
 #[derive(Deserialize)]
 struct ResponseMessage {
-    request: String,
     response: String,
 }
 
+#[derive(Deserialize)]
+struct EmoteRequest {
+    emote: String,
+}
+
+#[derive(Serialize)]
+struct EmoteMessage<'a> {
+    message: &'a str,
+    player: &'a str,
+    emote: &'a str,
+}
+
+#[derive(Serialize)]
+struct BuzzRejectedMessage<'a> {
+    message: &'a str,
+    reason: &'a str,
+}
+
+#[derive(Serialize)]
+struct RegisterErrorMessage<'a> {
+    message: &'a str,
+    kind: &'a str,
+    reason: &'a str,
+}
+
+/// Close code for a player whose name is taken by a connected player.
+const NAME_TAKEN_CLOSE_CODE: u16 = 4409;
+/// Close code for a player turned away from a full lobby.
+const LOBBY_FULL_CLOSE_CODE: u16 = 4403;
+
+/// Why a player couldn't join a game.
+#[derive(Debug, PartialEq)]
+pub enum RegisterError {
+    /// A player with the same name is already connected.
+    NameTaken,
+    /// The name isn't allowed, for the given reason.
+    Invalid(&'static str),
+    /// The game already has as many players as it allows.
+    LobbyFull,
+}
+
+impl RegisterError {
+    fn kind(&self) -> &'static str {
+        match self {
+            RegisterError::NameTaken => "name_taken",
+            RegisterError::Invalid(_) => "invalid_name",
+            RegisterError::LobbyFull => "lobby_full",
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self {
+            RegisterError::NameTaken => "A player with that name is already connected",
+            RegisterError::Invalid(reason) => reason,
+            RegisterError::LobbyFull => "This game is full",
+        }
+    }
+
+    fn close_code(&self) -> u16 {
+        match self {
+            RegisterError::NameTaken => NAME_TAKEN_CLOSE_CODE,
+            RegisterError::Invalid(_) => BAD_REQUEST_CLOSE_CODE,
+            RegisterError::LobbyFull => LOBBY_FULL_CLOSE_CODE,
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct PlayerInputResponseMessage {
     message: String,
     valid: bool,
     reason: String,
+    /// The wager that was accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<i32>,
+    /// The response that was stored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response: Option<String>,
+}
+
+const MAX_NAME_CHARS: usize = 24;
+
+/// The smallest wager accepted on anything.
+pub const MIN_WAGER: i32 = 5;
+
+/// How many times one player name may connect to a lobby within
+/// `RECONNECT_WINDOW_MS` before further attempts are turned away.
+const RECONNECT_LIMIT: usize = 5;
+const RECONNECT_WINDOW_MS: u128 = 10_000;
+
+/// Comma-separated player names banned in every game, in the same form as
+/// `GameConfig::banned_names`.
+const BANNED_NAMES_NAME: &str = "BANNED_PLAYER_NAMES";
+
+/// Whether `name` matches a banned name pattern, ignoring case.
+fn matches_banned(name: &str, pattern: &str) -> bool {
+    let name = name.to_lowercase();
+    let pattern = pattern.trim().to_lowercase();
+    match pattern.strip_prefix('*').and_then(|p| p.strip_suffix('*')) {
+        Some(part) => !part.is_empty() && name.contains(part),
+        None => !pattern.is_empty() && name == pattern,
+    }
+}
+
+/// Trims a player name and collapses runs of whitespace inside it, so names
+/// that only differ in spacing refer to the same player.
+fn normalize_name(name: &str) -> Result<String, &'static str> {
+    if name.chars().any(char::is_control) {
+        return Err("Name can't contain control characters");
+    }
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if name.is_empty() {
+        return Err("Name can't be empty");
+    }
+    if name.chars().count() > MAX_NAME_CHARS {
+        return Err("Name is too long (max 24 characters)");
+    }
+    Ok(name)
 }
 
 impl Game {
+    /// Records a connection attempt under `name`, returning false if it's
+    /// one too many for the reconnect window. Attempts older than the window
+    /// are forgotten.
+    fn allow_connect(&mut self, name: &str) -> bool {
+        let now = self.now();
+        self.connect_attempts.retain(|_, attempts| {
+            while attempts
+                .front()
+                .is_some_and(|t| now.saturating_sub(*t) > RECONNECT_WINDOW_MS)
+            {
+                attempts.pop_front();
+            }
+            !attempts.is_empty()
+        });
+
+        let attempts = self.connect_attempts.entry(name.to_string()).or_default();
+        if attempts.len() >= RECONNECT_LIMIT {
+            return false;
+        }
+        attempts.push_back(now);
+        true
+    }
+
+    fn is_banned_name(&self, name: &str) -> bool {
+        let server_wide = env::var(BANNED_NAMES_NAME).unwrap_or_default();
+        server_wide
+            .split(',')
+            .chain(self.config.banned_names.iter().map(String::as_str))
+            .any(|pattern| matches_banned(name, pattern))
+    }
+
+    /// Adds a player, or reconnects them if they've been here before, with
+    /// the color and avatar they asked for. A reconnecting player keeps
+    /// whatever they don't ask to change. Returns the name they were
+    /// registered under.
     fn register_player(
         &mut self,
         name: &str,
-        tx: mpsc::UnboundedSender<Message>,
-    ) -> Result<(), ()> {
-        let name = name.to_owned();
-        if self.state.players.contains_key(&name) {
-            if let Some(Some(_tx)) = self.state.players.get(&name).map(|p| &p.tx) {
-                return Err(());
+        tx: mpsc::UnboundedSender<Frame>,
+        color: Option<String>,
+        avatar: Option<String>,
+    ) -> Result<String, RegisterError> {
+        let name = normalize_name(name).map_err(RegisterError::Invalid)?;
+        if color.as_deref().is_some_and(|c| !is_hex_color(c)) {
+            return Err(RegisterError::Invalid("Color must look like #rrggbb"));
+        }
+        if avatar
+            .as_ref()
+            .is_some_and(|a| !self.config.avatar_allowlist.contains(a))
+        {
+            return Err(RegisterError::Invalid("That avatar isn't available"));
+        }
+
+        match self.state.players.get(&name) {
+            Some(Player { tx: Some(_), .. }) => return Err(RegisterError::NameTaken),
+            Some(_) => {}
+            None => {
+                if self.is_banned_name(&name) {
+                    return Err(RegisterError::Invalid("That name isn't allowed"));
+                }
+                if let Some(max) = self.config.max_players {
+                    if self.state.players.len() >= max {
+                        return Err(RegisterError::LobbyFull);
+                    }
+                }
+                self.insert_player(name.clone(), None, 0);
             }
+        }
 
-            self.state
-                .players
-                .entry(name)
-                .and_modify(move |p| p.tx = Some(tx));
-        } else {
-            self.state.player_responses.insert(name.clone(), None);
-            self.state.wagers.insert(name.clone(), None);
-            self.state.players.insert(
-                name.clone(),
-                Player {
-                    name,
-                    tx: Some(tx),
-                    balance: 0,
-                    did_auth: false,
-                },
-            );
+        if let Some(player) = self.state.players.get_mut(&name) {
+            player.tx = Some(tx);
+            if let Some(color) = color {
+                player.color = color;
+            }
+            if avatar.is_some() {
+                player.avatar = avatar;
+            }
         }
+        self.joined = true;
+        Ok(name)
+    }
 
+    fn insert_player(
+        &mut self,
+        name: String,
+        tx: Option<mpsc::UnboundedSender<Frame>>,
+        balance: i32,
+    ) {
+        let now = self.now();
+        self.state.player_responses.insert(name.clone(), None);
+        self.state.wagers.insert(name.clone(), None);
+        self.state.players.insert(
+            name.clone(),
+            Player {
+                color: default_color(&name),
+                avatar: None,
+                name,
+                tx,
+                balance,
+                did_auth: false,
+                history: vec![(now, balance)],
+                last_emote: None,
+            },
+        );
+    }
+
+    /// Reserves seats for players who haven't connected yet, so they can
+    /// claim them by name later. Nothing is added unless every seat is valid.
+    pub fn reserve_players(&mut self, seats: Vec<PlayerSeat>) -> Result<(), String> {
+        let mut names = HashSet::new();
+        let mut reserved = Vec::with_capacity(seats.len());
+        for seat in seats {
+            let name = normalize_name(&seat.name).map_err(|e| format!("{:?}: {}", seat.name, e))?;
+            if self.state.players.contains_key(&name) || !names.insert(name.clone()) {
+                return Err(format!("{:?}: duplicate player name", name));
+            }
+            if self.is_banned_name(&name) {
+                return Err(format!("{:?}: That name isn't allowed", name));
+            }
+            reserved.push((name, seat.balance));
+        }
+
+        for (name, balance) in reserved {
+            self.insert_player(name, None, balance);
+        }
+        self.send_state();
         Ok(())
     }
 
     fn player_disconnected(&mut self, name: String) {
+        let was_ready = self.state.ready_players.remove(&name);
         self.state.players.entry(name).and_modify(move |p| {
             if let Some(tx) = &p.tx {
-                tx.send(Message::close());
+                let _ = tx.send(Frame::close());
             }
             p.tx = None;
         });
+        if was_ready {
+            self.send_ready_count();
+        }
+    }
+
+    fn ready(&mut self, name: &str) {
+        if self.state.ready_players.insert(name.to_string()) {
+            self.send_ready_count();
+        }
     }
 
     pub fn buzz(&mut self, name: &str) {
@@ -96,31 +362,104 @@ impl Game {
 
         self.state.buzzers_open = false;
         self.state.buzzed_player = Some(name.to_string());
+        self.state.buzzed_at = Some(self.now());
         self.state.responded_players.insert(name.to_string());
+        self.start_answer_timer();
+        self.send_state();
+    }
+
+    /// A buzz sent by the player themself, as opposed to one forced by a
+    /// Daily Double or final response. Applies the house rules on who may
+    /// buzz. `received` is when the buzz came off the socket.
+    pub fn player_buzz(&mut self, name: &str, received: Instant) {
+        let balance = match self.state.players.get(name) {
+            Some(p) => p.balance,
+            None => return,
+        };
+        if !self.config.allow_negative_buzz && balance < 0 {
+            if !self.state.buzzers_open {
+                return;
+            }
+            let msg = BuzzRejectedMessage {
+                message: "buzz_rejected",
+                reason: "You can't buzz in with a negative balance",
+            };
+            if let (Some(Some(tx)), Ok(txt)) = (
+                self.state.players.get(name).map(|p| &p.tx),
+                serde_json::to_string(&msg),
+            ) {
+                let _ = tx.send(Frame::text(txt));
+            }
+            return;
+        }
+
+        if self.config.buzz_window_ms == 0 {
+            self.buzz(name);
+        } else if self.state.buzzers_open && !self.state.responded_players.contains(name) {
+            self.queue_buzz(name, received);
+        }
+    }
+
+    /// Takes back an accidental buzz if it happened within the configured
+    /// grace window, reopening the buzzers to everyone.
+    fn unbuzz(&mut self, name: &str) {
+        if let RoundType::FinalRound { .. } = self.rounds[self.state.round_idx] {
+            return;
+        }
+        if self.state.buzzed_player.as_deref() != Some(name) {
+            return;
+        }
+        if self.config.unbuzz_grace_ms == 0 {
+            return;
+        }
+        let grace = u128::from(self.config.unbuzz_grace_ms);
+        match self.state.buzzed_at {
+            Some(t) if self.now().saturating_sub(t) < grace => {}
+            _ => return,
+        }
+
+        self.cancel_timer();
+        self.state.buzzed_player = None;
+        self.state.buzzed_at = None;
+        self.state.responded_players.remove(name);
+        self.state.buzzers_open = true;
         self.send_state();
     }
 
     fn response(&mut self, name: String, response: String) {
-        let msg = if response.is_empty() {
+        let choices = self.current_clue().and_then(|c| c.choices.as_ref());
+        if self.state.state_type == StateType::Clue && choices.is_some() {
+            self.choose(name, &response);
+            return;
+        }
+
+        let msg = if self.state.state_type != StateType::FinalClue {
+            PlayerInputResponseMessage {
+                message: "input-response".to_string(),
+                valid: false,
+                reason: "Responses aren't being taken right now".to_string(),
+                amount: None,
+                response: None,
+            }
+        } else if response.is_empty() {
             PlayerInputResponseMessage {
                 message: "input-response".to_string(),
                 valid: false,
                 reason: "Input cannot be empty".to_string(),
+                amount: None,
+                response: None,
             }
         } else {
             PlayerInputResponseMessage {
                 message: "input-response".to_string(),
                 valid: true,
                 reason: "Input received".to_string(),
+                amount: None,
+                response: Some(response.clone()),
             }
         };
 
-        if let Some(Some(tx)) = self.state.players.get(&name).map(|p| &p.tx) {
-            if let Ok(txt) = serde_json::to_string(&msg) {
-                tx.send(Message::text(txt));
-            }
-        }
-
+        self.send_input_response(&name, &msg);
         if !msg.valid {
             return;
         }
@@ -128,48 +467,177 @@ impl Game {
         self.state.player_responses.insert(name, Some(response));
 
         if self.state.player_responses.values().all(Option::is_some) {
+            self.send_final_responses();
             self.evaluate_final_responses();
+        } else {
+            self.send_final_pending();
+        }
+    }
+
+    /// Takes the buzzed player's pick on a multiple-choice clue, given as
+    /// the index of the choice. The host still judges it.
+    fn choose(&mut self, name: String, response: &str) {
+        let (num_choices, correct_choice) = match self.current_clue() {
+            Some(Clue {
+                choices: Some(choices),
+                correct_choice,
+                ..
+            }) => (choices.len(), *correct_choice),
+            _ => return,
+        };
+        let index = response
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|i| *i < num_choices);
+
+        let reason = if self.state.buzzed_player.as_ref() != Some(&name) {
+            Err("It isn't your turn to respond")
+        } else if self.state.chosen.is_some() {
+            Err("You've already picked a choice")
+        } else if index.is_none() {
+            Err("Pick one of the choices")
+        } else {
+            Ok("Input received")
+        };
+        let valid = reason.is_ok();
+        let msg = PlayerInputResponseMessage {
+            message: "input-response".to_string(),
+            valid,
+            reason: reason.unwrap_or_else(|e| e).to_string(),
+            amount: None,
+            response: index.filter(|_| valid).map(|i| i.to_string()),
+        };
+        self.send_input_response(&name, &msg);
+        if !msg.valid {
+            return;
+        }
+
+        self.state.chosen = index;
+        if self.config.auto_judge {
+            self.state.suggested_correct = Some(index == correct_choice);
+        }
+        self.send_state();
+    }
+
+    fn send_input_response(&self, name: &str, msg: &PlayerInputResponseMessage) {
+        if let Some(Some(tx)) = self.state.players.get(name).map(|p| &p.tx) {
+            if let Ok(txt) = serde_json::to_string(msg) {
+                let _ = tx.send(Frame::text(txt));
+            }
+        }
+    }
+
+    /// Relays an allowed emote from a player to the board and host, at most
+    /// once per cooldown period. Game state is untouched.
+    fn emote(&mut self, name: &str, emote: &str) {
+        if !self.config.emote_allowlist.iter().any(|e| e == emote) {
+            return;
+        }
+
+        let now = self.now();
+        let cooldown = u128::from(self.config.emote_cooldown_ms);
+        let player = match self.state.players.get_mut(name) {
+            Some(p) => p,
+            None => return,
+        };
+        if let Some(last) = player.last_emote {
+            if now.saturating_sub(last) < cooldown {
+                return;
+            }
+        }
+        player.last_emote = Some(now);
+
+        let msg = EmoteMessage {
+            message: "emote",
+            player: name,
+            emote,
+        };
+        if let Ok(s) = serde_json::to_string(&msg) {
+            let msg = Frame::text(s);
+            self.send_to_board(msg.clone());
+            self.send_to_host(msg);
         }
     }
 
     fn get_max_wager(&self, player: &str) -> i32 {
         let buzzed_player_balance = self.state.players[player].balance;
-        let default_max_wager = match self.rounds[self.state.round_idx] {
+        let round = &self.rounds[self.state.round_idx];
+        let default_max_wager = match round {
             RoundType::DefaultRound {
                 default_max_wager, ..
-            } => default_max_wager,
+            } => default_max_wager.saturating_mul(round.value_multiplier()),
             RoundType::FinalRound {
                 default_max_wager, ..
-            } => default_max_wager,
+            } => *default_max_wager,
         };
-        cmp::max(buzzed_player_balance, default_max_wager)
+        let max = cmp::max(buzzed_player_balance, default_max_wager);
+        match self.config.daily_double_cap {
+            Some(cap) if self.state.state_type == StateType::DailyDouble => cmp::min(max, cap),
+            _ => max,
+        }
     }
 
-    fn wager(&mut self, player: String, wager: i32) {
+    pub fn wager(&mut self, player: String, wager: i32) {
+        let wagered_clue = matches!(
+            self.state.state_type,
+            StateType::DailyDouble | StateType::Wager
+        );
         let max = self.get_max_wager(&player);
-        let msg: PlayerInputResponseMessage = if wager > max {
-            PlayerInputResponseMessage {
-                message: "input-response".to_string(),
-                valid: false,
-                reason: format!("Wager too high (max wager: {})", max),
-            }
-        } else if wager < 5 {
-            PlayerInputResponseMessage {
-                message: "input-response".to_string(),
-                valid: false,
-                reason: "Wager too low (min wager: 5)".to_string(),
-            }
-        } else {
-            PlayerInputResponseMessage {
-                message: "input-response".to_string(),
-                valid: true,
-                reason: "Wager accepted".to_string(),
-            }
-        };
+        // Only the player in control of the board wagers on a Daily Double
+        // or wager clue; the host can hand control over with "player". Before
+        // anyone has control, whoever wagers first takes it.
+        let out_of_turn = self
+            .state
+            .active_player
+            .as_ref()
+            .is_some_and(|active| *active != player);
+        let msg: PlayerInputResponseMessage =
+            if !wagered_clue && self.state.state_type != StateType::FinalWager {
+                PlayerInputResponseMessage {
+                    message: "input-response".to_string(),
+                    valid: false,
+                    reason: "Wagers aren't being taken right now".to_string(),
+                    amount: None,
+                    response: None,
+                }
+            } else if wagered_clue && out_of_turn {
+                PlayerInputResponseMessage {
+                    message: "input-response".to_string(),
+                    valid: false,
+                    reason: "It isn't your turn to wager".to_string(),
+                    amount: None,
+                    response: None,
+                }
+            } else if wager > max {
+                PlayerInputResponseMessage {
+                    message: "input-response".to_string(),
+                    valid: false,
+                    reason: format!("Wager too high (max wager: {})", max),
+                    amount: None,
+                    response: None,
+                }
+            } else if wager < MIN_WAGER {
+                PlayerInputResponseMessage {
+                    message: "input-response".to_string(),
+                    valid: false,
+                    reason: format!("Wager too low (min wager: {})", MIN_WAGER),
+                    amount: None,
+                    response: None,
+                }
+            } else {
+                PlayerInputResponseMessage {
+                    message: "input-response".to_string(),
+                    valid: true,
+                    reason: "Wager accepted".to_string(),
+                    amount: Some(wager),
+                    response: None,
+                }
+            };
 
         if let Some(Some(tx)) = self.state.players.get(&player).map(|p| &p.tx) {
             if let Ok(txt) = serde_json::to_string(&msg) {
-                tx.send(Message::text(txt));
+                let _ = tx.send(Frame::text(txt));
             }
         }
 
@@ -177,10 +645,11 @@ impl Game {
             return;
         }
 
-        if self.state.state_type == StateType::DailyDouble {
+        if wagered_clue {
             self.state.cost = wager;
             self.state.state_type = StateType::Clue;
-            self.state.active_player = None;
+            self.state.wagered_clue = true;
+            self.set_active_player(Some(player.clone()));
             self.state.buzzers_open = true;
             self.buzz(&player);
 
@@ -192,24 +661,25 @@ impl Game {
             return;
         }
         self.state.wagers.insert(player, Some(wager));
-        let (clue, response) = match &self.rounds[self.state.round_idx] {
-            RoundType::DefaultRound { .. } => return,
-            RoundType::FinalRound { clue, response, .. } => (clue, response),
-        };
         if self.state.wagers.values().all(|w| w.is_some()) {
-            self.state.state_type = StateType::FinalClue;
-            self.state.clue = clue.clone();
-            self.state.response = response.clone();
-            self.send_state();
+            self.show_final_clue();
+        } else {
+            self.send_final_pending();
         }
     }
 }
 
-pub async fn player_connected(games: AsyncGameList, lobby_id: String, ws: WebSocket) {
+#[tracing::instrument(skip(games, ws))]
+pub async fn player_connected(
+    games: AsyncGameList,
+    lobby_id: String,
+    ws: WebSocket,
+    format: Format,
+) {
     let game = match games.read().await.get(&lobby_id) {
         Some(Some(game)) => game.clone(),
         _ => {
-            ws.close().await;
+            let _ = ws.close().await;
             return;
         }
     };
@@ -221,100 +691,606 @@ pub async fn player_connected(games: AsyncGameList, lobby_id: String, ws: WebSoc
         let msg = match result {
             Ok(msg) => msg,
             Err(e) => {
-                eprintln!("websocket error: {}", e);
+                tracing::error!("websocket error: {}", e);
+                if let Some(close) = close_for_ws_error(&e) {
+                    let _ = ws_tx.send(format.encode(&close)).await;
+                }
                 return;
             }
         };
 
-        let msg = match msg.to_str() {
-            Ok(s) => s,
-            Err(_) => {
-                eprintln!("websocket error: non-string message received");
+        let msg = match format.decode(&msg) {
+            Some(s) => s,
+            None => {
+                tracing::error!("websocket error: non-string message received");
                 return;
             }
         };
 
-        let m: ConnectMessage = match serde_json::from_str(msg) {
+        let m: ConnectMessage = match serde_json::from_str(&msg) {
             Ok(m) => m,
             Err(e) => {
-                eprintln!("serde error: {}", e);
+                tracing::error!("serde error: {}", for_log(&e.to_string()));
+                let _ = ws_tx
+                    .send(Message::close_with(BAD_REQUEST_CLOSE_CODE, "bad request"))
+                    .await;
                 return;
             }
         };
 
-        tokio::task::spawn(async move {
-            while let Some(message) = rx.next().await {
-                ws_tx
-                    .send(message)
-                    .unwrap_or_else(|e| {
-                        eprintln!("websocket send error: {}", e);
-                    })
+        // Claim the name before anything else can happen on this connection,
+        // so of several sockets connecting as the same player exactly one
+        // gets it.
+        let registered = {
+            let mut game = game.write().await;
+            let key = normalize_name(&m.name).unwrap_or_else(|_| m.name.clone());
+            if !game.allow_connect(&key) {
+                drop(game);
+                tracing::warn!("throttling reconnects from player {:?}", for_log(&m.name));
+                let _ = ws_tx
+                    .send(Message::close_with(
+                        RECONNECT_THROTTLED_CLOSE_CODE,
+                        "reconnecting too often",
+                    ))
                     .await;
+                return;
             }
-        });
-
-        {
-            let mut game = game.write().await;
-            if let Err(_) = game.register_player(&m.name, tx) {
+            let registered = game.register_player(&m.name, tx.clone(), m.color, m.avatar);
+            if registered.is_ok() {
+                // The board isn't necessarily there to have sent these.
+                let _ = tx.send(game.categories_message());
+                game.send_state();
+            }
+            registered
+        };
+        let name = match registered {
+            Ok(name) => name,
+            Err(e) => {
+                tracing::info!("rejected player {:?}: {}", for_log(&m.name), e.reason());
+                let msg = RegisterErrorMessage {
+                    message: "register_error",
+                    kind: e.kind(),
+                    reason: e.reason(),
+                };
+                if let Ok(txt) = serde_json::to_string(&msg) {
+                    let _ = ws_tx.send(format.encode(&Frame::text(txt))).await;
+                }
+                let _ = ws_tx
+                    .send(Message::close_with(e.close_code(), e.kind()))
+                    .await;
                 return;
             }
-            game.send_state();
-        }
+        };
+
+        tokio::task::spawn(
+            async move {
+                while let Some(message) = rx.next().await {
+                    ws_tx
+                        .send(format.encode(&message))
+                        .unwrap_or_else(|e| {
+                            tracing::error!("websocket send error: {}", e);
+                        })
+                        .await;
+                }
+            }
+            .in_current_span(),
+        );
 
         while let Some(result) = ws_rx.next().await {
+            // Taken before anything else, so time spent waiting on the game
+            // lock doesn't count against a buzz.
+            let received = Instant::now();
             let msg = match result {
                 Ok(msg) => msg,
                 Err(e) => {
-                    eprintln!("websocket error: {}", e);
+                    tracing::error!("websocket error: {}", e);
+                    if let Some(close) = close_for_ws_error(&e) {
+                        let _ = tx.send(close);
+                    }
                     break;
                 }
             };
 
-            let txt = match msg.to_str() {
-                Ok(s) => s,
-                Err(_) => {
+            let txt = match format.decode(&msg) {
+                Some(s) => s,
+                None => {
                     if msg.is_close() {
-                        game.write().await.player_disconnected(m.name.clone());
+                        game.write().await.player_disconnected(name.clone());
                     }
-                    eprintln!("websocket error: non-string message received");
+                    tracing::error!("websocket error: non-string message received");
                     continue;
                 }
             };
 
-            let msg: BaseMessage = match serde_json::from_str(txt) {
-                Ok(m) => m,
-                Err(e) => {
-                    eprintln!("Deserialization Error: {}", e);
-                    break;
-                }
+            let msg = match parse_base_message(&txt, &tx) {
+                Some(m) => m,
+                None => break,
             };
 
+            game.write().await.touch();
             match msg.request.as_str() {
-                "buzz" => game.write().await.buzz(&m.name),
+                "buzz" => game.write().await.player_buzz(&name, received),
+                "unbuzz" => game.write().await.unbuzz(&name),
                 "response" => {
-                    let msg: ResponseMessage = match serde_json::from_str(txt) {
-                        Ok(m) => m,
-                        Err(e) => {
-                            eprintln!("Deserialization Error: {}", e);
-                            break;
-                        }
+                    let msg: ResponseMessage = match parse_request(&txt, &tx) {
+                        Some(m) => m,
+                        None => continue,
                     };
-                    game.write().await.response(m.name.clone(), msg.response);
+                    game.write().await.response(name.clone(), msg.response);
                 }
                 "wager" => {
-                    let msg: WagerMessage = match serde_json::from_str(txt) {
-                        Ok(m) => m,
-                        Err(e) => {
-                            eprintln!("Deserialization Error: {}", e);
-                            break;
-                        }
+                    let msg: WagerMessage = match parse_request(&txt, &tx) {
+                        Some(m) => m,
+                        None => continue,
+                    };
+                    game.write().await.wager(name.clone(), msg.amount);
+                }
+                "ready" => game.write().await.ready(&name),
+                "emote" => {
+                    let msg: EmoteRequest = match parse_request(&txt, &tx) {
+                        Some(m) => m,
+                        None => continue,
                     };
-                    game.write().await.wager(m.name.clone(), msg.amount);
+                    game.write().await.emote(&name, &msg.emote);
+                }
+                "sync" => {
+                    let game = game.read().await;
+                    if let Some(Some(tx)) = game.state.players.get(&name).map(|p| &p.tx) {
+                        let _ = tx.send(game.player_state_message(&name));
+                    }
                 }
-                _ => {}
+                _ => reject_request(&msg.request, &tx),
+            }
+            game.write().await.enforce_invariants();
+        }
+
+        game.write().await.player_disconnected(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::lib::harness::{drain, last, test_game};
+
+    #[tokio::test]
+    async fn unbuzz_only_within_the_grace_window() {
+        let (game, clock) = test_game(json!({ "unbuzz_grace_ms": 500 }));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice"]);
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+
+        game.player_buzz("alice", Instant::now());
+        clock.advance(499);
+        game.unbuzz("alice");
+        assert_eq!(game.state.buzzed_player, None);
+        assert!(game.state.buzzers_open);
+
+        game.player_buzz("alice", Instant::now());
+        clock.advance(500);
+        game.unbuzz("alice");
+        assert_eq!(game.state.buzzed_player.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn no_grace_means_no_unbuzz() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice"]);
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+
+        game.player_buzz("alice", Instant::now());
+        game.unbuzz("alice");
+        assert_eq!(game.state.buzzed_player.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn negative_balance_blocks_buzzing_when_configured() {
+        let (game, _clock) = test_game(json!({ "allow_negative_buzz": false }));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice", "bob"]);
+        game.state.players.get_mut("bob").unwrap().balance = -200;
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+
+        game.player_buzz("bob", Instant::now());
+        assert_eq!(game.state.buzzed_player, None);
+        let rejected = last(clients.players.get_mut("bob").unwrap(), "buzz_rejected");
+        assert!(rejected.is_some());
+
+        game.player_buzz("alice", Instant::now());
+        assert_eq!(game.state.buzzed_player.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn negative_balance_can_buzz_by_default() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["bob"]);
+        game.state.players.get_mut("bob").unwrap().balance = -200;
+        assert!(game.reveal(0, 0));
+        game.set_buzzers_open(true);
+
+        game.player_buzz("bob", Instant::now());
+        assert_eq!(game.state.buzzed_player.as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn names_are_trimmed_and_collapsed() {
+        assert_eq!(normalize_name("  alice  ").unwrap(), "alice");
+        assert_eq!(normalize_name("mary   ann").unwrap(), "mary ann");
+        assert_eq!(normalize_name(&"x".repeat(24)).unwrap(), "x".repeat(24));
+    }
+
+    #[test]
+    fn bad_names_are_rejected() {
+        assert!(normalize_name("").is_err());
+        assert!(normalize_name("   ").is_err());
+        assert!(normalize_name("al\u{7}ice").is_err());
+        assert!(normalize_name("mary\tann").is_err());
+        assert!(normalize_name(&"x".repeat(25)).is_err());
+    }
+
+    #[tokio::test]
+    async fn names_differing_in_spacing_are_the_same_player() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let (tx, _rx) = mpsc::unbounded_channel();
+        assert_eq!(
+            game.register_player("alice", tx.clone(), None, None),
+            Ok("alice".to_string())
+        );
+        assert_eq!(
+            game.register_player(" alice ", tx, None, None),
+            Err(RegisterError::NameTaken)
+        );
+        assert_eq!(game.state.players.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn responses_outside_a_final_clue_are_ignored() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice", "bob"]);
+
+        game.response("alice".to_string(), "the Nile".to_string());
+        assert_eq!(game.state.player_responses["alice"], None);
+        let reply = last(clients.players.get_mut("alice").unwrap(), "input-response").unwrap();
+        assert_eq!(reply["valid"], false);
+
+        assert!(game.reveal(0, 0));
+        game.response("alice".to_string(), "the Nile".to_string());
+        assert_eq!(game.state.player_responses["alice"], None);
+        assert_eq!(game.state.state_type, StateType::Clue);
+
+        game.next_round();
+        game.state.state_type = StateType::FinalClue;
+        game.response("alice".to_string(), "the Pacific".to_string());
+        assert_eq!(
+            game.state.player_responses["alice"].as_deref(),
+            Some("the Pacific")
+        );
+    }
+
+    #[tokio::test]
+    async fn only_the_active_player_wagers_and_keeps_control() {
+        for correct in [false, true] {
+            let (game, _clock) = test_game(json!({}));
+            let mut game = game.write().await;
+            let mut clients = game.attach_test_clients(&["alice", "bob"]);
+            game.set_active_player(Some("alice".to_string()));
+            assert!(game.reveal(1, 0));
+
+            game.wager("bob".to_string(), 500);
+            assert_eq!(game.state.state_type, StateType::DailyDouble);
+            let reply = last(clients.players.get_mut("bob").unwrap(), "input-response");
+            assert_eq!(reply.unwrap()["valid"], false);
+
+            game.wager("alice".to_string(), 500);
+            assert_eq!(game.state.buzzed_player.as_deref(), Some("alice"));
+            game.correct(correct);
+            assert_eq!(game.state.active_player.as_deref(), Some("alice"));
+            let balance = if correct { 500 } else { -500 };
+            assert_eq!(game.state.players["alice"].balance, balance);
+            assert_eq!(game.state.buzzed_player, None);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_connects_with_one_name_have_one_winner() {
+        let (game, _clock) = test_game(json!({}));
+        let connects: Vec<_> = (0..64)
+            .map(|_| {
+                let game = game.clone();
+                tokio::spawn(async move {
+                    let (tx, rx) = mpsc::unbounded_channel();
+                    let result = game.write().await.register_player("alice", tx, None, None);
+                    (result, rx)
+                })
+            })
+            .collect();
+
+        let mut winners = 0;
+        let mut receivers = vec![];
+        for connect in connects {
+            let (result, rx) = connect.await.unwrap();
+            match result {
+                Ok(_) => winners += 1,
+                Err(e) => assert_eq!(e, RegisterError::NameTaken),
             }
+            receivers.push(rx);
         }
+        assert_eq!(winners, 1);
+        assert_eq!(game.read().await.state.players.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rapid_reconnects_are_throttled() {
+        let (game, clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        for _ in 0..RECONNECT_LIMIT {
+            assert!(game.allow_connect("alice"));
+            clock.advance(100);
+        }
+        assert!(!game.allow_connect("alice"));
+        assert!(game.allow_connect("bob"));
+
+        clock.advance(RECONNECT_WINDOW_MS as u64);
+        assert!(game.allow_connect("alice"));
+    }
+
+    #[tokio::test]
+    async fn each_register_failure_has_its_own_error() {
+        let (game, _clock) = test_game(json!({ "max_players": 1 }));
+        let mut game = game.write().await;
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut register = |name: &str, color: Option<&str>, avatar: Option<&str>| {
+            game.register_player(
+                name,
+                tx.clone(),
+                color.map(String::from),
+                avatar.map(String::from),
+            )
+        };
+
+        assert!(matches!(
+            register("", None, None),
+            Err(RegisterError::Invalid(_))
+        ));
+        assert!(matches!(
+            register("alice", Some("red"), None),
+            Err(RegisterError::Invalid(_))
+        ));
+        assert!(matches!(
+            register("alice", None, Some("dragon")),
+            Err(RegisterError::Invalid(_))
+        ));
+        assert_eq!(
+            register("alice", Some("#00ff00"), Some("owl")),
+            Ok("alice".to_string())
+        );
+        assert_eq!(register("alice", None, None), Err(RegisterError::NameTaken));
+        assert_eq!(register("bob", None, None), Err(RegisterError::LobbyFull));
+    }
+
+    #[test]
+    fn register_errors_close_with_distinct_codes() {
+        assert_eq!(RegisterError::NameTaken.close_code(), NAME_TAKEN_CLOSE_CODE);
+        assert_eq!(RegisterError::LobbyFull.close_code(), LOBBY_FULL_CLOSE_CODE);
+        assert_eq!(
+            RegisterError::Invalid("bad").close_code(),
+            BAD_REQUEST_CLOSE_CODE
+        );
+        assert_eq!(RegisterError::Invalid("bad").reason(), "bad");
+    }
+
+    #[test]
+    fn banned_patterns_match_ignoring_case() {
+        assert!(matches_banned("Host", "host"));
+        assert!(!matches_banned("hostess", "host"));
+        assert!(matches_banned("The HOST", "*host*"));
+        assert!(!matches_banned("alice", "*host*"));
+        assert!(!matches_banned("alice", ""));
+        assert!(!matches_banned("alice", "**"));
+    }
+
+    #[tokio::test]
+    async fn banned_names_are_rejected_and_near_misses_allowed() {
+        let (game, _clock) = test_game(json!({ "banned_names": ["admin", "*host*"] }));
+        let mut game = game.write().await;
+        let (tx, _rx) = mpsc::unbounded_channel();
+
+        for name in ["Admin", "real host", "HOSTED"] {
+            assert!(matches!(
+                game.register_player(name, tx.clone(), None, None),
+                Err(RegisterError::Invalid(_))
+            ));
+        }
+        for name in ["admins", "hos t"] {
+            assert!(game.register_player(name, tx.clone(), None, None).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn emotes_reach_the_board_and_host_only() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice", "bob"]);
+        drain(&mut clients.board);
+        drain(&mut clients.host);
+
+        game.emote("alice", "👏");
+        let relayed = json!({ "message": "emote", "player": "alice", "emote": "👏" });
+        assert_eq!(drain(&mut clients.board), vec![relayed.clone()]);
+        assert_eq!(drain(&mut clients.host), vec![relayed]);
+        for rx in clients.players.values_mut() {
+            assert!(last(rx, "emote").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn emotes_off_the_allowlist_are_dropped() {
+        let (game, _clock) = test_game(json!({ "emote_allowlist": ["👏"] }));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice"]);
+        drain(&mut clients.board);
+
+        game.emote("alice", "🔥");
+        game.emote("alice", "<script>");
+        assert!(drain(&mut clients.board).is_empty());
+        assert!(game.state.players["alice"].last_emote.is_none());
+    }
+
+    #[tokio::test]
+    async fn emotes_inside_the_cooldown_are_dropped() {
+        let (game, clock) = test_game(json!({ "emote_cooldown_ms": 2000 }));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice", "bob"]);
+        drain(&mut clients.board);
+
+        game.emote("alice", "👏");
+        clock.advance(1999);
+        game.emote("alice", "😂");
+        game.emote("bob", "😮");
+        let emotes: Vec<_> = drain(&mut clients.board)
+            .into_iter()
+            .map(|msg| msg["emote"].clone())
+            .collect();
+        assert_eq!(emotes, vec![json!("👏"), json!("😮")]);
+
+        clock.advance(1);
+        game.emote("alice", "😂");
+        assert_eq!(last(&mut clients.board, "emote").unwrap()["emote"], "😂");
+    }
+
+    /// Reads from `client` until a message of the given kind arrives.
+    async fn recv_kind(client: &mut warp::test::WsClient, kind: &str) -> serde_json::Value {
+        loop {
+            let msg = client.recv().await.unwrap();
+            let msg: serde_json::Value = serde_json::from_str(msg.to_str().unwrap()).unwrap();
+            if msg["message"] == kind {
+                return msg;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_emote_keeps_the_socket_open() {
+        use std::{collections::HashMap, sync::Arc};
+        use tokio::sync::RwLock;
+        use warp::Filter;
+
+        let (game, _clock) = test_game(json!({}));
+        let mut clients = game.write().await.attach_test_clients(&[]);
+        let games: crate::lib::AsyncGameList = Arc::new(RwLock::new(HashMap::from([(
+            "lobby".to_string(),
+            Some(game),
+        )])));
+        let route = warp::ws().map(move |ws: warp::ws::Ws| {
+            let games = games.clone();
+            ws.on_upgrade(move |ws| player_connected(games, "lobby".to_string(), ws, Format::Json))
+        });
+        let mut client = warp::test::ws().handshake(route).await.unwrap();
+
+        client.send_text(r#"{"name":"alice"}"#).await;
+        recv_kind(&mut client, "state").await;
+        client.send_text(r#"{"request":"emote","emote":7}"#).await;
+        assert_eq!(recv_kind(&mut client, "error").await["kind"], "bad_request");
+
+        client
+            .send_text(r#"{"request":"emote","emote":"👏"}"#)
+            .await;
+        client.send_text(r#"{"request":"sync"}"#).await;
+        recv_kind(&mut client, "state").await;
+        assert_eq!(
+            last(&mut clients.board, "emote").unwrap()["player"],
+            "alice"
+        );
+    }
+
+    #[tokio::test]
+    async fn banned_names_cant_be_reserved() {
+        let (game, _clock) = test_game(json!({ "banned_names": ["*host*"] }));
+        let mut game = game.write().await;
+        let seats = |names: &[&str]| -> Vec<PlayerSeat> {
+            serde_json::from_value(json!(names
+                .iter()
+                .map(|name| json!({ "name": name }))
+                .collect::<Vec<_>>()))
+            .unwrap()
+        };
+
+        let err = game
+            .reserve_players(seats(&["alice", "  Real   HOST "]))
+            .unwrap_err();
+        assert!(err.contains("\"Real HOST\""), "{}", err);
+        assert!(game.state.players.is_empty());
+
+        assert!(game.reserve_players(seats(&["alice", "hos t"])).is_ok());
+        assert_eq!(game.state.players.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn daily_double_wagers_respect_the_cap() {
+        for (cap, max) in [(json!(2000), 2000), (json!(null), 5000)] {
+            let (game, _clock) = test_game(json!({ "daily_double_cap": cap }));
+            let mut game = game.write().await;
+            let mut clients = game.attach_test_clients(&["alice"]);
+            game.state.players.get_mut("alice").unwrap().balance = 5000;
+            game.set_active_player(Some("alice".to_string()));
+            assert!(game.reveal(1, 0));
+            assert_eq!(game.get_max_wager("alice"), max);
+
+            game.wager("alice".to_string(), max + 1);
+            let reply = last(clients.players.get_mut("alice").unwrap(), "input-response");
+            assert_eq!(
+                reply.unwrap()["reason"],
+                format!("Wager too high (max wager: {})", max)
+            );
+            game.wager("alice".to_string(), max);
+            assert_eq!(game.state.cost, max);
+        }
+    }
+
+    #[tokio::test]
+    async fn first_daily_double_can_be_wagered_before_anyone_has_control() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let _clients = game.attach_test_clients(&["alice", "bob"]);
+        assert_eq!(game.state.active_player, None);
+        assert!(game.reveal(1, 0));
+
+        game.wager("bob".to_string(), 500);
+        assert_eq!(game.state.state_type, StateType::Clue);
+        assert_eq!(game.state.buzzed_player.as_deref(), Some("bob"));
+        assert_eq!(game.state.active_player.as_deref(), Some("bob"));
+    }
+
+    #[tokio::test]
+    async fn wagers_are_refused_when_nothing_is_being_wagered_on() {
+        let (game, _clock) = test_game(json!({}));
+        let mut game = game.write().await;
+        let mut clients = game.attach_test_clients(&["alice"]);
+
+        game.wager("alice".to_string(), 500);
+        assert_eq!(game.state.state_type, StateType::Board);
+        assert!(game.reveal(0, 0));
+        game.wager("alice".to_string(), 500);
+        assert_eq!(game.state.state_type, StateType::Clue);
+        assert_eq!(game.state.cost, 200);
+        assert_eq!(game.state.wagers["alice"], None);
 
-        game.write().await.player_disconnected(m.name);
+        let replies: Vec<_> = drain(clients.players.get_mut("alice").unwrap())
+            .into_iter()
+            .filter(|msg| msg["message"] == "input-response")
+            .collect();
+        assert_eq!(replies.len(), 2);
+        assert!(replies
+            .iter()
+            .all(|r| r["reason"] == "Wagers aren't being taken right now"));
     }
 }
@@ -0,0 +1,23 @@
+/// The most characters of client-supplied text that make it into a log line.
+const MAX_LOGGED_CHARS: usize = 64;
+
+/// Makes client-supplied text safe to log: control characters, including
+/// newlines, are escaped so they can't forge log lines, and long input is
+/// cut short.
+pub fn for_log(s: &str) -> String {
+    let mut out: String = s
+        .chars()
+        .take(MAX_LOGGED_CHARS)
+        .flat_map(|c| {
+            if c.is_control() {
+                c.escape_default().collect::<Vec<_>>()
+            } else {
+                vec![c]
+            }
+        })
+        .collect();
+    if s.chars().nth(MAX_LOGGED_CHARS).is_some() {
+        out.push('…');
+    }
+    out
+}
@@ -0,0 +1,83 @@
+use serde::Serialize;
+
+use super::{game::RoundType, Game, Round};
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CategorySummary {
+    pub category: String,
+    pub clues_total: usize,
+    pub clues_shown: usize,
+    /// What every clue in the category is worth put together.
+    pub points_available: i32,
+    /// What players won from the category's clues. Misses aren't
+    /// subtracted.
+    pub points_won: i32,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RoundSummary {
+    pub name: String,
+    pub categories: Vec<CategorySummary>,
+}
+
+impl Game {
+    /// How much of each category of the current round has been played. None
+    /// for final rounds, which have no board.
+    pub fn round_summary(&self) -> Option<RoundSummary> {
+        let round = &self.rounds[self.state.round_idx];
+        let categories = match round {
+            RoundType::DefaultRound { categories, .. } => categories,
+            RoundType::FinalRound { .. } => return None,
+        };
+        let multiplier = round.value_multiplier();
+
+        let categories = categories
+            .iter()
+            .enumerate()
+            .map(|(col, category)| {
+                let mut summary = CategorySummary {
+                    category: category.category.clone(),
+                    clues_total: category.clues.len(),
+                    clues_shown: 0,
+                    points_available: 0,
+                    points_won: 0,
+                };
+                for (row, clue) in category.clues.iter().enumerate() {
+                    summary.points_available = summary
+                        .points_available
+                        .saturating_add(clue.cost.saturating_mul(multiplier));
+                    if self.state.is_clue_shown(row, col) {
+                        summary.clues_shown += 1;
+                    }
+                    summary.points_won = summary
+                        .points_won
+                        .saturating_add(*self.points_won.get(&(row, col)).unwrap_or(&0));
+                }
+                summary
+            })
+            .collect();
+
+        Some(RoundSummary {
+            name: round.get_name(),
+            categories,
+        })
+    }
+
+    /// Credits a correct response to the clue it was for.
+    pub fn record_points_won(&mut self, amount: i32) {
+        if let Some(cell) = self.state.current_cell {
+            let won = self.points_won.entry(cell).or_insert(0);
+            *won = won.saturating_add(amount);
+        }
+    }
+
+    /// Files away the current round's summary before moving on from it.
+    pub fn archive_round_summary(&mut self) {
+        if let Some(summary) = self.round_summary() {
+            self.round_summaries.push(summary);
+        }
+        self.points_won.clear();
+        self.state.cell_outcomes.clear();
+        self.state.current_cell = None;
+    }
+}
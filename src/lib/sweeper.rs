@@ -0,0 +1,181 @@
+use std::{
+    env,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use tokio::sync::RwLock;
+use tracing::Instrument;
+
+use super::{AsyncGameList, AsyncIdStore, Game};
+
+const LOBBY_TTL_NAME: &str = "LOBBY_TTL_SECS";
+const DEFAULT_LOBBY_TTL_SECS: u64 = 6 * 60 * 60;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+const EMPTY_LOBBY_GRACE_NAME: &str = "EMPTY_LOBBY_GRACE_SECS";
+const DEFAULT_EMPTY_LOBBY_GRACE_SECS: u64 = 5 * 60;
+
+/// Ends `game` and hands its ID back to the store if `lobby_id` still names
+/// it and it's still `abandoned`. The game list is only locked for the
+/// removal itself: the game's lock is tried rather than awaited under it, so
+/// a game that's busy right now is left alone.
+async fn end_if_abandoned(
+    games: &AsyncGameList,
+    id_store: &AsyncIdStore,
+    lobby_id: &str,
+    game: &Arc<RwLock<Game>>,
+    abandoned: impl Fn(&Game) -> bool,
+) -> bool {
+    {
+        let mut games = games.write().await;
+        // The lobby may have been ended already, and its ID even reused for
+        // a different game.
+        match games.get(lobby_id) {
+            Some(Some(current)) if Arc::ptr_eq(current, game) => {}
+            _ => return false,
+        }
+        match game.try_read() {
+            Ok(game) if abandoned(&game) => {}
+            _ => return false,
+        }
+        games.remove(lobby_id);
+    }
+
+    game.write().await.end();
+    id_store.write().await.release(lobby_id.to_string());
+    true
+}
+
+/// Ends a newly created lobby if nobody has connected to it by the end of
+/// the grace period, handing its ID back to the store.
+pub fn end_if_never_joined(
+    games: AsyncGameList,
+    id_store: AsyncIdStore,
+    lobby_id: String,
+    game: Weak<RwLock<Game>>,
+) {
+    let grace_secs = env::var(EMPTY_LOBBY_GRACE_NAME)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_EMPTY_LOBBY_GRACE_SECS);
+
+    tokio::spawn(
+        async move {
+            tokio::time::sleep(Duration::from_secs(grace_secs)).await;
+
+            let game = match game.upgrade() {
+                Some(game) => game,
+                None => return,
+            };
+            if game.read().await.joined {
+                return;
+            }
+            if end_if_abandoned(&games, &id_store, &lobby_id, &game, |g| !g.joined).await {
+                tracing::info!(lobby_id = %lobby_id, "ended lobby nobody joined");
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+/// Periodically ends lobbies that nobody is connected to and that haven't
+/// seen any activity within the TTL, handing their IDs back to the store.
+pub async fn sweep_lobbies(games: AsyncGameList, id_store: AsyncIdStore) {
+    let ttl_secs = env::var(LOBBY_TTL_NAME)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_LOBBY_TTL_SECS);
+    let ttl_millis = u128::from(ttl_secs) * 1000;
+    let abandoned = |game: &Game| {
+        let idle = game.now().saturating_sub(game.last_activity);
+        !game.has_connections() && idle > ttl_millis
+    };
+
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let lobbies: Vec<(String, Arc<RwLock<Game>>)> = games
+            .read()
+            .await
+            .iter()
+            .filter_map(|(lobby_id, game)| Some((lobby_id.clone(), game.clone()?)))
+            .collect();
+
+        for (lobby_id, game) in lobbies {
+            if !abandoned(&*game.read().await) {
+                continue;
+            }
+            if end_if_abandoned(&games, &id_store, &lobby_id, &game, abandoned).await {
+                tracing::info!(lobby_id = %lobby_id, "ended abandoned lobby");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::lib::{harness::test_game, IdStore};
+
+    fn lobby_list(game: &Arc<RwLock<Game>>) -> (AsyncGameList, AsyncIdStore) {
+        let games = HashMap::from([("lobby".to_string(), Some(game.clone()))]);
+        (
+            Arc::new(RwLock::new(games)),
+            Arc::new(RwLock::new(IdStore::new())),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_busy_game_doesnt_hold_up_the_game_list() {
+        let (game, _clock) = test_game(json!({}));
+        game.write().await.joined = false;
+        let (games, id_store) = lobby_list(&game);
+
+        let busy = game.write().await;
+        end_if_never_joined(
+            games.clone(),
+            id_store,
+            "lobby".to_string(),
+            Arc::downgrade(&game),
+        );
+        tokio::time::sleep(Duration::from_secs(DEFAULT_EMPTY_LOBBY_GRACE_SECS + 1)).await;
+        assert!(games.try_write().is_ok());
+
+        drop(busy);
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert!(games.read().await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_joined_lobby_is_kept() {
+        let (game, _clock) = test_game(json!({}));
+        game.write().await.joined = true;
+        let (games, id_store) = lobby_list(&game);
+
+        end_if_never_joined(
+            games.clone(),
+            id_store,
+            "lobby".to_string(),
+            Arc::downgrade(&game),
+        );
+        tokio::time::sleep(Duration::from_secs(DEFAULT_EMPTY_LOBBY_GRACE_SECS + 1)).await;
+        assert!(games.read().await.contains_key("lobby"));
+    }
+
+    #[tokio::test]
+    async fn a_lobby_whose_id_was_reused_is_left_alone() {
+        let (game, _clock) = test_game(json!({}));
+        let (replacement, _clock) = test_game(json!({}));
+        let (games, id_store) = lobby_list(&replacement);
+
+        assert!(!end_if_abandoned(&games, &id_store, "lobby", &game, |_| true).await);
+        assert!(games.read().await.contains_key("lobby"));
+        assert!(end_if_abandoned(&games, &id_store, "lobby", &replacement, |_| true).await);
+        assert!(games.read().await.is_empty());
+    }
+}
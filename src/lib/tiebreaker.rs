@@ -0,0 +1,115 @@
+use serde::Serialize;
+
+use super::{format::Frame, game::StateType, Game};
+
+#[derive(Serialize)]
+struct TieMessage<'a> {
+    message: &'a str,
+    players: &'a [String],
+}
+
+#[derive(Serialize)]
+struct TiebreakerWinnerMessage<'a> {
+    message: &'a str,
+    player: &'a str,
+}
+
+impl Game {
+    /// Everyone sharing the top balance, if more than one player does.
+    fn tied_leaders(&self) -> Vec<String> {
+        let top = match self.state.players.values().map(|p| p.balance).max() {
+            Some(b) => b,
+            None => return vec![],
+        };
+        let mut leaders: Vec<String> = self
+            .state
+            .players
+            .values()
+            .filter(|p| p.balance == top)
+            .map(|p| p.name.clone())
+            .collect();
+        if leaders.len() < 2 {
+            return vec![];
+        }
+        leaders.sort_unstable();
+        leaders
+    }
+
+    /// Called once the last final clue has been judged. Starts a sudden-death
+    /// clue for the players tied for first if the game has one, otherwise
+    /// just announces the tie. Returns false if nobody is tied.
+    pub fn start_tiebreaker(&mut self) -> bool {
+        let leaders = self.tied_leaders();
+        if leaders.is_empty() {
+            return false;
+        }
+
+        let clue = match &self.tiebreaker {
+            Some(c) => c.clone(),
+            None => {
+                self.send_tie(&leaders);
+                return false;
+            }
+        };
+
+        tracing::info!("starting tiebreaker between {:?}", leaders);
+        self.state.in_tiebreaker = true;
+        self.state.state_type = StateType::Clue;
+        self.state.category = "Tiebreaker".to_string();
+        self.state.clue = clue.clue;
+        self.state.response = clue.response;
+        self.state.choices = None;
+        self.state.cost = 0;
+        self.state.buzzed_player = None;
+        self.state.responded_players = self
+            .state
+            .players
+            .keys()
+            .filter(|p| !leaders.contains(p))
+            .cloned()
+            .collect();
+        self.state.buzzers_open = true;
+        self.send_state();
+        true
+    }
+
+    /// Judges a tiebreaker buzz: the first correct response wins outright.
+    pub fn judge_tiebreaker(&mut self, correct: bool) {
+        let player = match self.state.buzzed_player.take() {
+            Some(p) => p,
+            None => return,
+        };
+
+        if correct {
+            let msg = TiebreakerWinnerMessage {
+                message: "tiebreaker_winner",
+                player: &player,
+            };
+            if let Ok(s) = serde_json::to_string(&msg) {
+                self.send_to_all(Frame::text(s));
+            }
+        } else if self.state.responded_players.len() < self.state.players.len() {
+            self.state.buzzers_open = true;
+            self.send_state();
+            return;
+        } else {
+            let leaders = self.tied_leaders();
+            self.send_tie(&leaders);
+        }
+
+        self.state.in_tiebreaker = false;
+        self.state.buzzers_open = false;
+        self.state.state_type = StateType::Response;
+        self.send_state();
+    }
+
+    fn send_tie(&self, players: &[String]) {
+        let msg = TieMessage {
+            message: "tie",
+            players,
+        };
+        if let Ok(s) = serde_json::to_string(&msg) {
+            self.send_to_all(Frame::text(s));
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Timers sleep on tokio's clock rather than the game's [`Clock`], so tests
+//! drive them by pausing tokio time (`#[tokio::test(start_paused = true)]`)
+//! and sleeping past the deadline.
+//!
+//! [`Clock`]: super::Clock
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::Instrument;
+
+use super::{game::RoundType, Game};
+
+impl Game {
+    /// Gives the buzzed player `answer_timeout_secs` to be judged, after
+    /// which they're counted wrong and the buzzers reopen. Final rounds are
+    /// judged at the host's pace and never time out.
+    pub fn start_answer_timer(&mut self) {
+        self.cancel_timer();
+
+        let secs = self.config.answer_timeout_secs;
+        if secs == 0 {
+            return;
+        }
+        if let RoundType::FinalRound { .. } = self.rounds[self.state.round_idx] {
+            return;
+        }
+
+        let generation = self.timer_generation;
+        self.state.timer_end_secs = Some((self.now() / 1000) as u64 + secs);
+        let handle = self.handle.clone();
+        tokio::spawn(
+            async move {
+                tokio::time::sleep(Duration::from_secs(secs)).await;
+                let game = match handle.upgrade() {
+                    Some(g) => g,
+                    None => return,
+                };
+                let mut game = game.write().await;
+                if game.timer_generation == generation && game.state.buzzed_player.is_some() {
+                    tracing::info!("answer timed out");
+                    game.correct(false);
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    /// Collects buzzes for `buzz_window_ms` after the first one was received,
+    /// then gives the clue to whoever's buzz arrived earliest.
+    pub fn queue_buzz(&mut self, name: &str, received: Instant) {
+        let first = self.pending_buzzes.is_empty();
+        if self.pending_buzzes.iter().all(|(_, n)| n != name) {
+            self.pending_buzzes.push((received, name.to_string()));
+        }
+        if !first {
+            return;
+        }
+
+        let generation = self.timer_generation;
+        let deadline = received + Duration::from_millis(self.config.buzz_window_ms);
+        let handle = self.handle.clone();
+        tokio::spawn(
+            async move {
+                tokio::time::sleep_until(deadline).await;
+                let game = match handle.upgrade() {
+                    Some(g) => g,
+                    None => return,
+                };
+                let mut game = game.write().await;
+                if game.timer_generation == generation {
+                    game.resolve_buzzes();
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    fn resolve_buzzes(&mut self) {
+        let mut pending = std::mem::take(&mut self.pending_buzzes);
+        pending.sort_by_key(|(received, _)| *received);
+        let winner = pending
+            .into_iter()
+            .map(|(_, name)| name)
+            .find(|name| self.state.players.contains_key(name));
+        if let Some(name) = winner {
+            self.buzz(&name);
+        }
+    }
+
+    /// Stops any running timer from firing.
+    pub fn cancel_timer(&mut self) {
+        self.pending_buzzes.clear();
+        self.timer_generation += 1;
+        self.state.timer_end_secs = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::lib::harness::test_game;
+
+    #[tokio::test(start_paused = true)]
+    async fn unjudged_answer_times_out() {
+        let (game, clock) = test_game(json!({ "answer_timeout_secs": 5 }));
+        clock.set(60_000);
+        let _clients = {
+            let mut game = game.write().await;
+            let clients = game.attach_test_clients(&["alice", "bob"]);
+            assert!(game.reveal(0, 0));
+            game.set_buzzers_open(true);
+            game.player_buzz("alice", Instant::now());
+            assert_eq!(game.state.timer_end_secs, Some(65));
+            clients
+        };
+
+        tokio::time::sleep(Duration::from_secs(4)).await;
+        assert_eq!(
+            game.read().await.state.buzzed_player.as_deref(),
+            Some("alice")
+        );
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let game = game.read().await;
+        assert_eq!(game.state.buzzed_player, None);
+        assert_eq!(game.state.players["alice"].balance, -200);
+        assert!(game.state.buzzers_open);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn buzz_window_goes_to_the_earliest_buzz() {
+        let (game, _clock) = test_game(json!({ "buzz_window_ms": 100 }));
+        let _clients = {
+            let mut game = game.write().await;
+            let clients = game.attach_test_clients(&["alice", "bob"]);
+            assert!(game.reveal(0, 0));
+            game.set_buzzers_open(true);
+            clients
+        };
+
+        // Alice's buzz arrives first but bob's gets the game lock first.
+        let alice_at = Instant::now();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        {
+            let mut game = game.write().await;
+            game.player_buzz("bob", Instant::now());
+            game.player_buzz("alice", alice_at);
+            assert_eq!(game.state.buzzed_player, None);
+        }
+
+        tokio::time::sleep(Duration::from_millis(101)).await;
+        assert_eq!(
+            game.read().await.state.buzzed_player.as_deref(),
+            Some("alice")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn buzz_window_runs_from_when_the_first_buzz_arrived() {
+        let (game, _clock) = test_game(json!({ "buzz_window_ms": 100 }));
+        let _clients = {
+            let mut game = game.write().await;
+            let clients = game.attach_test_clients(&["alice"]);
+            assert!(game.reveal(0, 0));
+            game.set_buzzers_open(true);
+            clients
+        };
+
+        // The buzz waits 60ms for the game lock before it's queued.
+        let received = Instant::now();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        game.write().await.player_buzz("alice", received);
+
+        tokio::time::sleep(Duration::from_millis(41)).await;
+        assert_eq!(
+            game.read().await.state.buzzed_player.as_deref(),
+            Some("alice")
+        );
+    }
+}
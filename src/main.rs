@@ -1,8 +1,15 @@
+#![allow(special_module_name)]
+
 use crate::lib::AsyncGameList;
 use crate::lib::IdStore;
 use lib::{
-    handlers::{accept_board, start_game, AsyncIdStore},
-    host_connected, player_connected, Game, Round, RoundType, State,
+    handlers::{
+        accept_board, game_board, game_connections, game_history, game_summary, get_definition,
+        import_players, recover_unauthorized, require_admin_token, start_game, update_definition,
+        AsyncGameSource, AsyncIdStore, AsyncIdempotencyKeys, StartQuery,
+    },
+    host_connected, player_connected, sweep_lobbies, Clue, FsGameSource, Game, GameConfig, Round,
+    RoundType, WrongAnswerPenalty, WsQuery, MAX_BOARD_CELLS, MAX_CLUE_COST, MIN_WAGER,
 };
 use opentelemetry::trace::TracerProvider;
 use opentelemetry::{global, KeyValue};
@@ -23,17 +30,190 @@ use tracing::Level;
 use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
 
-use std::{error::Error, sync::Arc};
+use std::{
+    env,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    process,
+    sync::Arc,
+};
 use tokio::sync::RwLock;
 
 use warp::Filter;
 
 pub mod lib;
 
+const MAX_DEFINITION_BYTES: u64 = 1024 * 256;
+const MAX_PLAYERS_BYTES: u64 = 1024 * 16;
+const WS_MAX_MESSAGE_BYTES_NAME: &str = "WS_MAX_MESSAGE_BYTES";
+const DEFAULT_WS_MAX_MESSAGE_BYTES: usize = 1024 * 64;
+const MAX_CATEGORY_CHARS_NAME: &str = "MAX_CATEGORY_CHARS";
+const DEFAULT_MAX_CATEGORY_CHARS: usize = 100;
+const MAX_CLUE_CHARS_NAME: &str = "MAX_CLUE_CHARS";
+const DEFAULT_MAX_CLUE_CHARS: usize = 1000;
+const MAX_RESPONSE_CHARS_NAME: &str = "MAX_RESPONSE_CHARS";
+const DEFAULT_MAX_RESPONSE_CHARS: usize = 500;
+
+/// Reads a length limit from the environment, falling back to `default` if
+/// it's unset or not a number.
+fn env_limit(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(default)
+}
+
 #[derive(Deserialize)]
-struct GameDefinition {
+pub struct GameDefinition {
     rounds: Vec<RoundType>,
+    #[serde(default)]
+    config: GameConfig,
+    /// A sudden-death clue for when the game ends in a tie for first.
+    #[serde(default)]
+    tiebreaker: Option<Clue>,
+    /// Shown in place of the usual welcome text before the first clue.
+    #[serde(default)]
+    welcome_category: Option<String>,
+    #[serde(default)]
+    welcome_clue: Option<String>,
+    #[serde(default)]
+    welcome_response: Option<String>,
+}
+
+impl GameDefinition {
+    fn validate(&self) -> Result<(), String> {
+        if self.rounds.is_empty() {
+            return Err("game has no rounds".to_string());
+        }
+        self.check_text_lengths()?;
+        if let WrongAnswerPenalty::Fraction(f) = self.config.wrong_answer_penalty {
+            if !(0.0..=1.0).contains(&f) {
+                return Err(format!(
+                    "wrong_answer_penalty fraction {} is outside 0 to 1",
+                    f
+                ));
+            }
+        }
+        if let Some(cap) = self.config.daily_double_cap {
+            if cap < MIN_WAGER {
+                return Err(format!(
+                    "daily_double_cap {} is below the minimum wager of {}",
+                    cap, MIN_WAGER
+                ));
+            }
+        }
+        let in_range = |v: i32| (0..=MAX_CLUE_COST).contains(&v);
+        for round in &self.rounds {
+            let (name, default_max_wager) = match round {
+                RoundType::DefaultRound {
+                    name,
+                    default_max_wager,
+                    ..
+                }
+                | RoundType::FinalRound {
+                    name,
+                    default_max_wager,
+                    ..
+                } => (name, *default_max_wager),
+            };
+            let multiplier = round.value_multiplier();
+            if multiplier < 1 {
+                return Err(format!("round {} has a value multiplier below 1", name));
+            }
+            if !in_range(default_max_wager.saturating_mul(multiplier)) {
+                return Err(format!("round {} has an out-of-range max wager", name));
+            }
+
+            if let RoundType::DefaultRound { categories, .. } = round {
+                if categories.is_empty() {
+                    return Err(format!("round {} has no categories", name));
+                }
+                if let Some(c) = categories.iter().find(|c| c.clues.is_empty()) {
+                    return Err(format!(
+                        "category {} in round {} has no clues",
+                        c.category, name
+                    ));
+                }
+                if let Some((cols, rows)) = round.dimensions() {
+                    if cols * rows > MAX_BOARD_CELLS {
+                        return Err(format!(
+                            "round {} is {}x{}, more than {} clues",
+                            name, cols, rows, MAX_BOARD_CELLS
+                        ));
+                    }
+                }
+                let bad_choices = categories.iter().flat_map(|c| &c.clues).find(|clue| {
+                    match (&clue.choices, clue.correct_choice) {
+                        (None, None) => false,
+                        (Some(choices), Some(i)) => i >= choices.len(),
+                        _ => true,
+                    }
+                });
+                if let Some(clue) = bad_choices {
+                    return Err(format!(
+                        "clue \"{}\" in round {} needs both choices and a correct_choice among them",
+                        clue.clue, name
+                    ));
+                }
+                let costs = categories
+                    .iter()
+                    .flat_map(|c| c.clues.iter().map(|clue| clue.cost));
+                if costs
+                    .map(|c| c.saturating_mul(multiplier))
+                    .any(|c| !in_range(c))
+                {
+                    return Err(format!(
+                        "round {} has a clue cost outside 0..={}",
+                        name, MAX_CLUE_COST
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects category names, clues and responses longer than the limits
+    /// set by MAX_CATEGORY_CHARS, MAX_CLUE_CHARS and MAX_RESPONSE_CHARS,
+    /// since all of them end up in every state broadcast.
+    fn check_text_lengths(&self) -> Result<(), String> {
+        let max_category = env_limit(MAX_CATEGORY_CHARS_NAME, DEFAULT_MAX_CATEGORY_CHARS);
+        let max_clue = env_limit(MAX_CLUE_CHARS_NAME, DEFAULT_MAX_CLUE_CHARS);
+        let max_response = env_limit(MAX_RESPONSE_CHARS_NAME, DEFAULT_MAX_RESPONSE_CHARS);
+
+        let mut texts = vec![];
+        for round in &self.rounds {
+            if let RoundType::DefaultRound { categories, .. } = round {
+                for category in categories {
+                    texts.push(("category", category.category.clone(), max_category));
+                    for clue in &category.clues {
+                        texts.push(("clue", clue.clue.clone(), max_clue));
+                        texts.push(("response", clue.response.clone(), max_response));
+                    }
+                }
+            }
+            for clue in round.final_clues() {
+                texts.push(("category", clue.category, max_category));
+                texts.push(("clue", clue.clue, max_clue));
+                texts.push(("response", clue.response, max_response));
+            }
+        }
+        if let Some(clue) = &self.tiebreaker {
+            texts.push(("clue", clue.clue.clone(), max_clue));
+            texts.push(("response", clue.response.clone(), max_response));
+        }
+
+        for (kind, text, max) in texts {
+            let len = text.chars().count();
+            if len > max {
+                return Err(format!(
+                    "a {} is {} characters long, more than the limit of {}",
+                    kind, len, max
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 async fn end_game(games: AsyncGameList, lobby_id: String) -> String {
@@ -49,6 +229,20 @@ async fn end_game(games: AsyncGameList, lobby_id: String) -> String {
 struct Lobby {
     lobby_id: String,
     created: u128,
+    player_count: usize,
+}
+
+#[derive(Deserialize)]
+struct LobbyQuery {
+    /// Only list games that do (or don't) have someone connected.
+    active: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct Version {
+    name: &'static str,
+    version: &'static str,
+    git_sha: &'static str,
 }
 
 #[derive(Serialize)]
@@ -57,6 +251,103 @@ struct GameDetails {
     categories: Vec<String>,
 }
 
+const ALLOWED_ORIGINS_NAME: &str = "ALLOWED_ORIGINS";
+const ALLOWED_METHODS_NAME: &str = "ALLOWED_METHODS";
+const ALLOWED_HEADERS_NAME: &str = "ALLOWED_HEADERS";
+const DEFAULT_ALLOWED_METHODS: [&str; 3] = ["GET", "POST", "PUT"];
+const DEFAULT_ALLOWED_HEADERS: [&str; 2] = ["content-type", "authorization"];
+
+const BIND_ADDR_NAME: &str = "BIND_ADDR";
+const PORT_NAME: &str = "PORT";
+const DEFAULT_BIND_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+const DEFAULT_PORT: u16 = 10001;
+
+/// The address to listen on, from BIND_ADDR and PORT. Exits if either is set
+/// but can't be parsed.
+fn bind_addr() -> SocketAddr {
+    let ip = match env::var(BIND_ADDR_NAME) {
+        Ok(s) => s.parse().unwrap_or_else(|e| {
+            tracing::error!("invalid {} {:?}: {}", BIND_ADDR_NAME, s, e);
+            process::exit(1);
+        }),
+        Err(_) => DEFAULT_BIND_ADDR,
+    };
+    let port = match env::var(PORT_NAME) {
+        Ok(s) => s.parse().unwrap_or_else(|e| {
+            tracing::error!("invalid {} {:?}: {}", PORT_NAME, s, e);
+            process::exit(1);
+        }),
+        Err(_) => DEFAULT_PORT,
+    };
+    SocketAddr::new(ip, port)
+}
+
+const TLS_CERT_PATH_NAME: &str = "TLS_CERT_PATH";
+const TLS_KEY_PATH_NAME: &str = "TLS_KEY_PATH";
+
+/// The certificate and key to serve TLS with, if both TLS_CERT_PATH and
+/// TLS_KEY_PATH are set. Exits if only one of them is.
+fn tls_paths() -> Option<(String, String)> {
+    match (env::var(TLS_CERT_PATH_NAME), env::var(TLS_KEY_PATH_NAME)) {
+        (Ok(cert), Ok(key)) => Some((cert, key)),
+        (Err(_), Err(_)) => None,
+        _ => {
+            tracing::error!(
+                "{} and {} must be set together",
+                TLS_CERT_PATH_NAME,
+                TLS_KEY_PATH_NAME
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// Reads a comma-separated list from an environment variable, or None if the
+/// variable is unset or empty.
+fn env_list(name: &str) -> Option<Vec<String>> {
+    let list: Vec<String> = env::var(name)
+        .ok()?
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    if list.is_empty() {
+        None
+    } else {
+        Some(list)
+    }
+}
+
+/// Any origin is allowed when `origins` is None or "*"; otherwise only the
+/// listed origins are.
+fn build_cors(
+    origins: Option<Vec<String>>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+) -> warp::cors::Builder {
+    let cors = match origins {
+        Some(origins) if origins != ["*"] => {
+            warp::cors().allow_origins(origins.iter().map(String::as_str))
+        }
+        _ => warp::cors().allow_any_origin(),
+    };
+    cors.allow_methods(methods.iter().map(String::as_str))
+        .allow_headers(headers.iter().map(String::as_str))
+}
+
+/// Where new games are loaded from: the filesystem, unless the sqlite feature
+/// is enabled and GAME_DB_PATH points at a database.
+fn game_source() -> AsyncGameSource {
+    #[cfg(feature = "sqlite")]
+    if let Ok(path) = env::var("GAME_DB_PATH") {
+        match lib::SqliteGameSource::open(&path) {
+            Ok(source) => return Arc::new(source),
+            Err(e) => tracing::error!("couldn't open game database {}: {}", path, e),
+        }
+    }
+    Arc::new(FsGameSource::from_env())
+}
+
 fn resource() -> Resource {
     Resource::from_schema_url(
         [
@@ -86,12 +377,15 @@ fn init_tracer() -> Tracer {
     provider.tracer("rusty-jeopardy")
 }
 
+/// Log levels come from RUST_LOG (e.g. `RUST_LOG=jeopardy=debug`), or INFO
+/// if it's unset or invalid. The filter applies to both the fmt and
+/// OpenTelemetry layers.
 fn init_tracing_subscriber() {
     let tracer = init_tracer();
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(Level::INFO.to_string()));
     tracing_subscriber::registry()
-        .with(tracing_subscriber::filter::LevelFilter::from_level(
-            Level::INFO,
-        ))
+        .with(filter)
         .with(tracing_subscriber::fmt::layer())
         .with(OpenTelemetryLayer::new(tracer))
         .init();
@@ -103,34 +397,53 @@ async fn main() {
     let id_store: AsyncIdStore = Arc::new(RwLock::new(IdStore::new()));
 
     let games: AsyncGameList = Arc::new(RwLock::new(HashMap::new()));
+    tokio::spawn(sweep_lobbies(games.clone(), id_store.clone()));
+
     let games_filter = warp::any().map(move || games.clone());
 
+    let idempotency_keys: AsyncIdempotencyKeys = Arc::new(RwLock::new(HashMap::new()));
+    let idempotency_keys_filter = warp::any().map(move || idempotency_keys.clone());
+
+    let game_source = game_source();
+    let game_source_filter = warp::any().map(move || game_source.clone());
+
     let id_store_filter = warp::any().map(move || id_store.clone());
     let start_route = warp::post()
         .and(warp::path!("api" / "start" / usize))
+        .and(warp::query::<StartQuery>())
         .and(games_filter.clone())
         .and(id_store_filter)
+        .and(idempotency_keys_filter)
+        .and(game_source_filter)
         .and_then(start_game)
         .with(warp::trace::named("start_game"));
 
     let end_route = warp::post()
         .and(warp::path!("api" / "end" / String))
+        .and(require_admin_token())
         .and(games_filter.clone())
         .and_then(|lobby_id, games| async move {
             Ok::<String, warp::Rejection>(end_game(games, lobby_id).await)
         });
 
     let games_route = warp::path!("api" / "games")
+        .and(warp::query::<LobbyQuery>())
         .and(games_filter.clone())
-        .and_then(|games: AsyncGameList| async move {
+        .and_then(|query: LobbyQuery, games: AsyncGameList| async move {
             let games = games.read().await;
             let mut resp: Vec<Lobby> = Vec::with_capacity(games.len());
             for (lobby_id, game) in games.iter() {
                 if let Some(game) = game {
                     let game = game.read().await;
+                    if let Some(active) = query.active {
+                        if game.has_connections() != active {
+                            continue;
+                        }
+                    }
                     resp.push(Lobby {
                         lobby_id: lobby_id.to_string(),
                         created: game.created,
+                        player_count: game.state.players.len(),
                     })
                 }
             }
@@ -164,45 +477,234 @@ async fn main() {
             }
         });
 
+    let history_route = warp::path!("api" / "game" / String / "history")
+        .and(games_filter.clone())
+        .and_then(game_history);
+
+    let board_snapshot_route = warp::path!("api" / "game" / String / "board")
+        .and(games_filter.clone())
+        .and_then(game_board);
+
+    let summary_route = warp::path!("api" / "game" / String / "summary")
+        .and(games_filter.clone())
+        .and_then(game_summary);
+
+    let connections_route = warp::path!("api" / "game" / String / "connections")
+        .and(games_filter.clone())
+        .and_then(game_connections);
+
+    let definition_route = warp::put()
+        .and(warp::path!("api" / "game" / String / "definition"))
+        .and(require_admin_token())
+        .and(warp::body::content_length_limit(MAX_DEFINITION_BYTES))
+        .and(warp::body::json())
+        .and(games_filter.clone())
+        .and_then(update_definition);
+
+    let get_definition_route = warp::get()
+        .and(warp::path!("api" / "game" / String / "definition"))
+        .and(require_admin_token())
+        .and(games_filter.clone())
+        .and_then(get_definition);
+
+    let players_route = warp::post()
+        .and(warp::path!("api" / "game" / String / "players"))
+        .and(require_admin_token())
+        .and(warp::body::content_length_limit(MAX_PLAYERS_BYTES))
+        .and(warp::body::json())
+        .and(games_filter.clone())
+        .and_then(import_players);
+
+    let version_route = warp::path!("api" / "version").map(|| {
+        warp::reply::json(&Version {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: env!("GIT_SHA"),
+        })
+    });
+
+    // Oversized websocket messages are refused before they're buffered, let
+    // alone parsed.
+    let ws_max_message_bytes = env::var(WS_MAX_MESSAGE_BYTES_NAME)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_WS_MAX_MESSAGE_BYTES);
+    let ws_filter = warp::ws().map(move |ws: warp::ws::Ws| {
+        ws.max_message_size(ws_max_message_bytes)
+            .max_frame_size(ws_max_message_bytes)
+    });
+
     let buzzer_route = warp::path!("api" / "ws" / String / "buzzer")
-        .and(warp::ws())
+        .and(ws_filter)
+        .and(warp::query::<WsQuery>())
         .and(games_filter.clone())
-        .map(|lobby_id: String, ws: warp::ws::Ws, games: AsyncGameList| {
-            ws.on_upgrade(move |ws| player_connected(games, lobby_id, ws))
-        });
+        .map(
+            |lobby_id: String, ws: warp::ws::Ws, query: WsQuery, games: AsyncGameList| {
+                ws.on_upgrade(move |ws| player_connected(games, lobby_id, ws, query.format))
+            },
+        );
 
     let host_route = warp::path!("api" / "ws" / String / "host")
-        .and(warp::ws())
+        .and(ws_filter)
+        .and(warp::query::<WsQuery>())
         .and(games_filter.clone())
-        .map(|lobby_id: String, ws: warp::ws::Ws, games: AsyncGameList| {
-            ws.on_upgrade(move |ws| host_connected(games, lobby_id, ws))
-        });
+        .map(
+            |lobby_id: String, ws: warp::ws::Ws, query: WsQuery, games: AsyncGameList| {
+                ws.on_upgrade(move |ws| {
+                    host_connected(games, lobby_id, ws, query.format, query.takeover)
+                })
+            },
+        );
 
     let board_route = warp::path!("api" / "ws" / String / "board")
-        .and(warp::ws())
+        .and(ws_filter)
+        .and(warp::query::<WsQuery>())
         .and(games_filter.clone())
         .map(accept_board);
 
-    let cors = warp::cors::cors().allow_any_origin();
+    let cors = build_cors(
+        env_list(ALLOWED_ORIGINS_NAME),
+        env_list(ALLOWED_METHODS_NAME)
+            .unwrap_or_else(|| DEFAULT_ALLOWED_METHODS.map(String::from).to_vec()),
+        env_list(ALLOWED_HEADERS_NAME)
+            .unwrap_or_else(|| DEFAULT_ALLOWED_HEADERS.map(String::from).to_vec()),
+    );
 
     let http_routes = end_route
         .or(start_route)
         .or(games_route)
         .or(game_route)
+        .or(history_route)
+        .or(board_snapshot_route)
+        .or(summary_route)
+        .or(connections_route)
+        .or(definition_route)
+        .or(get_definition_route)
+        .or(players_route)
+        .or(version_route)
+        .recover(recover_unauthorized)
         .with(warp::trace::request());
 
-    warp::serve(
+    let addr = bind_addr();
+    let server = warp::serve(
         buzzer_route
             .or(host_route)
             .or(board_route)
             .or(http_routes)
             .with(cors),
-    )
-    .run(([0, 0, 0, 0], 10001))
-    .await;
+    );
+    match tls_paths() {
+        Some((cert, key)) => {
+            tracing::info!("serving over TLS on {}", addr);
+            server.tls().cert_path(cert).key_path(key).run(addr).await
+        }
+        None => server.run(addr).await,
+    }
 }
 
-enum JeopardyError {
-    DeserializationError,
-    ConnectionError,
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::lib::harness::test_definition;
+
+    async fn cors_status(origins: Option<&[&str]>, origin: &str) -> warp::http::StatusCode {
+        let cors = build_cors(
+            origins.map(|o| o.iter().map(|s| s.to_string()).collect()),
+            DEFAULT_ALLOWED_METHODS.map(String::from).to_vec(),
+            DEFAULT_ALLOWED_HEADERS.map(String::from).to_vec(),
+        );
+        let route = warp::any().map(warp::reply).with(cors);
+        warp::test::request()
+            .header("origin", origin)
+            .reply(&route)
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn cors_rejects_origins_not_allowed() {
+        let allowed: &[&str] = &["https://quiz.example"];
+        assert_eq!(
+            cors_status(Some(allowed), "https://quiz.example").await,
+            200
+        );
+        assert_eq!(
+            cors_status(Some(allowed), "https://evil.example").await,
+            403
+        );
+
+        assert_eq!(cors_status(None, "https://evil.example").await, 200);
+        assert_eq!(cors_status(Some(&["*"]), "https://evil.example").await, 200);
+    }
+
+    #[test]
+    fn overlong_text_is_rejected() {
+        let mut def = test_definition(json!({}));
+        if let RoundType::DefaultRound { categories, .. } = &mut def.rounds[0] {
+            categories[0].clues[0].clue = "x".repeat(DEFAULT_MAX_CLUE_CHARS);
+            categories[1].category = "x".repeat(DEFAULT_MAX_CATEGORY_CHARS);
+        }
+        assert!(def.validate().is_ok());
+
+        if let RoundType::DefaultRound { categories, .. } = &mut def.rounds[0] {
+            categories[0].clues[0].response = "x".repeat(DEFAULT_MAX_RESPONSE_CHARS + 1);
+        }
+        assert!(def.validate().is_err());
+
+        let mut def = test_definition(json!({}));
+        if let RoundType::FinalRound { category, .. } = &mut def.rounds[1] {
+            *category = "x".repeat(DEFAULT_MAX_CATEGORY_CHARS + 1);
+        }
+        assert!(def.validate().is_err());
+    }
+
+    #[test]
+    fn clue_costs_must_fit_the_ceiling() {
+        let mut def = test_definition(json!({}));
+        assert!(def.validate().is_ok());
+        if let RoundType::DefaultRound { categories, .. } = &mut def.rounds[0] {
+            categories[0].clues[0].cost = MAX_CLUE_COST + 1;
+        }
+        assert!(def.validate().is_err());
+
+        let mut def = test_definition(json!({}));
+        if let RoundType::DefaultRound {
+            value_multiplier, ..
+        } = &mut def.rounds[0]
+        {
+            *value_multiplier = Some(i32::MAX);
+        }
+        assert!(def.validate().is_err());
+    }
+
+    #[test]
+    fn penalty_fraction_must_be_between_zero_and_one() {
+        let def = |fraction: f32| {
+            let mut def = test_definition(json!({}));
+            def.config.wrong_answer_penalty = WrongAnswerPenalty::Fraction(fraction);
+            def
+        };
+        assert!(def(0.0).validate().is_ok());
+        assert!(def(0.5).validate().is_ok());
+        assert!(def(1.0).validate().is_ok());
+        assert!(def(1.5).validate().is_err());
+        assert!(def(-0.1).validate().is_err());
+        assert!(def(f32::NAN).validate().is_err());
+
+        let parsed = test_definition(json!({ "wrong_answer_penalty": { "fraction": 2.0 } }));
+        assert!(parsed.validate().is_err());
+    }
+
+    #[test]
+    fn daily_double_cap_must_allow_the_minimum_wager() {
+        let def = |cap: serde_json::Value| test_definition(json!({ "daily_double_cap": cap }));
+        assert!(def(json!(null)).validate().is_ok());
+        assert!(def(json!(MIN_WAGER)).validate().is_ok());
+        assert!(def(json!(2000)).validate().is_ok());
+        assert!(def(json!(MIN_WAGER - 1)).validate().is_err());
+        assert!(def(json!(0)).validate().is_err());
+        assert!(def(json!(-100)).validate().is_err());
+    }
 }